@@ -1,12 +1,11 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(prelude_2024)]
 
 use pc_keyboard::{DecodedKey, KeyCode};
-use pluggable_interrupt_os::vga_buffer::{BUFFER_WIDTH, BUFFER_HEIGHT, plot, ColorCode, Color, plot_str, is_drawable, plot_num};
-use csci320_vsfs::FileSystem;
-use simple_interp::{Interpreter, InterpreterOutput, i64_into_buffer};
-use gc_headers::GarbageCollectingHeap;
-// use gc_heap::CopyingHeap;
+use pluggable_interrupt_os::vga_buffer::{self, BUFFER_WIDTH, BUFFER_HEIGHT, ColorCode, Color, is_drawable};
+use csci320_vsfs::{FileSystem, FileSystemError};
+use simple_interp::{Interpreter, InterpreterOutput, TickStatus, i64_into_buffer};
+use gc_heap::CopyingHeap;
 
 // Get rid of some spurious VSCode errors
 use core::option::Option;
@@ -16,6 +15,7 @@ use core::clone::Clone;
 use core::cmp::{PartialEq,Eq};
 use core::marker::Copy;
 use core::str;
+use core::fmt::{self, Write};
 
 const FIRST_BORDER_ROW: usize = 1;
 const LAST_BORDER_ROW: usize = BUFFER_HEIGHT - 1;
@@ -24,14 +24,98 @@ const TASK_MANAGER_BYTES: usize = BUFFER_HEIGHT * TASK_MANAGER_WIDTH;
 const WINDOWS_WIDTH: usize = BUFFER_WIDTH - TASK_MANAGER_WIDTH;
 const WINDOW_WIDTH: usize = (WINDOWS_WIDTH - 3) / 2;
 const WINDOW_HEIGHT: usize = (LAST_BORDER_ROW - FIRST_BORDER_ROW - 2) / 2;
+// The size inside a maximized window, which takes the whole windows area and
+// so ends on the grid's bottom border, above the hint row.
+const MAX_WINDOW_WIDTH: usize = WINDOWS_WIDTH - 2;
+const MAX_WINDOW_HEIGHT: usize = 2 * WINDOW_HEIGHT + 1;
 const MID_WIDTH: usize = WINDOWS_WIDTH / 2;
 const MID_HEIGHT: usize = BUFFER_HEIGHT / 2;
 const NUM_WINDOWS: usize = 4;
-const WINDOW_LABEL_COL_OFFSET: usize = WINDOW_WIDTH - 3;
+// Each directory cell holds a name and its size in bytes, then a space.
+const DIRECTORY_COLUMNS: usize = 3;
+const TASK_MANAGER_COL: usize = WINDOWS_WIDTH;
+const TASK_ROWS_PER_WINDOW: usize = 5;
+const TASK_NAME_ROW: usize = 0;
+const TASK_RUNS_ROW: usize = 1;
+const TASK_MODE_ROW: usize = 2;
+const TASK_STEPS_ROW: usize = 3;
 const FILENAME_LABEL_COL_OFFSET: usize = 2;
+const RUN_STATE_LABEL_COL_OFFSET: usize = FILENAME_LABEL_COL_OFFSET + MAX_FILENAME_BYTES + 1;
 
 const FILENAME_PROMPT: &str = "F5 - Filename: ";
+const STATUS_COL: usize = FILENAME_PROMPT.len() + MAX_FILENAME_BYTES + 2;
+const INPUT_TARGET_PREFIX: &str = "typing: ";
+const INPUT_TARGET_WIDTH: usize = INPUT_TARGET_PREFIX.len() + "F1 (edit)".len();
+const INPUT_TARGET_COL: usize = BUFFER_WIDTH - INPUT_TARGET_WIDTH;
+const STATUS_WIDTH: usize = INPUT_TARGET_COL - 1 - STATUS_COL;
+const HINT_ROW: usize = BUFFER_HEIGHT - 1;
+const FILEBAR_HINT: &str = "Enter create  Bksp erase  Esc cancel  Tab/F1-F4 windows  Ins last  F12 reset";
+const RENAME_HINT: &str = "Enter rename file  Bksp erase  F1-F4 cancel";
+const COPY_HINT: &str = "Enter copy to this name  Bksp erase  F1-F4 cancel";
+const GO_TO_LINE_HINT: &str = "Enter go to line number  Bksp erase  F1-F4 cancel";
+const REPLACE_HINT: &str = "Enter replace old/new or old/new/g for all  Bksp erase  F1-F4 cancel";
+const SEARCH_HINT: &str = "Enter find text  Bksp erase  F1-F4 cancel  ^N in the editor finds next";
+const DIRECTORY_HINT: &str = "e edit  r run  R step  x .out  d del  n rename  c copy  i info  o last  s repl";
+const EDITING_HINT: &str = "F6 exit  ^S save  ^G goto  ^Z/Y undo  ^W/E find/repl  ^F fold  ^X/D cut/dup line";
+const RUNNING_HINT: &str = "F6 exit  r rerun  t ticks  w wrap  m pager  j same  o/Spc print  p/n step";
+const SYS_INFO_HINT: &str = "i or F6 back to directory  b box/ascii borders";
+const SCRATCH_HINT: &str = "Enter evaluate  Bksp erase  assignments carry over  F6 back to directory";
+const STATUS_QUEUE_LEN: usize = 4;
+const PROMPT_STACK_DEPTH: usize = 4;
+const STATUS_MIN_TICKS: usize = 18; // about a second at the default timer rate
+
+// The filebar prompt, a full-length name, at least some of the status
+// message, and the input target all have to fit on the top row.
+const _: () = assert!(STATUS_COL + 1 < INPUT_TARGET_COL);
 const EDIT_MODE_HEADER: &str = "(F6)";
+// Follows the filename of an edit buffer with unsaved changes.
+const DIRTY_MARKER: char = '*';
+const RUNS_LABEL: &str = "runs ";
+const BUSY_FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+const PAUSED_HEADER: &str = "PAUSED (step)";
+const PAUSED_ON_OUTPUT_HEADER: &str = "PAUSED (print)";
+const MORE_PROMPT: &str = "-- more --";
+const FINISHED_LABEL: &str = "finished in ";
+const HALTED_LABEL: &str = "halted after ";
+const STEPS_LABEL: &str = " steps";
+const OUTPUT_FILE_STEPS_LABEL: &str = ".out took ";
+const REVIEW_PROMPT: &str = "save these changes? y/n";
+const REVIEW_ENTRIES: usize = WINDOW_HEIGHT - 2;
+const NO_FILES_MESSAGE: &str = "(no files)";
+const SYS_INFO_HEADER: &str = "(i) system info";
+const SYS_INFO_VALUE_COL_OFFSET: usize = 21;
+const UNDRAWABLE_PLACEHOLDER: char = '?';
+const NO_FILES_HINT: &str = "F5 to create a file";
+const MAX_FOLDS: usize = 8;
+const UNDO_EDITS: usize = 64;
+const FOLD_PLACEHOLDER: &[u8] = b"...";
+const REINDENT_PROMPT: &str = "indent width 1-8? Esc cancels";
+// Text typed for a search or replace, which is longer than a filename can be.
+// It is shown on the editing window's bottom border.
+const TEXT_PROMPT_BYTES: usize = WINDOW_WIDTH - FILENAME_LABEL_COL_OFFSET;
+// Spaces typed for Tab; files never hold tab characters from the editor.
+const TAB_SPACES: usize = 4;
+
+const SHOW_BYTE_KEY: char = '\u{2}'; // Ctrl+B
+const INSERT_TICKS_KEY: char = '\u{14}'; // Ctrl+T
+const REVIEW_SAVES_KEY: char = '\u{16}'; // Ctrl+V
+const AUTOSAVE_KEY: char = '\u{1}'; // Ctrl+A
+const FOLD_KEY: char = '\u{6}'; // Ctrl+F
+const TRUNCATE_KEY: char = '\u{b}'; // Ctrl+K
+const MAXIMIZE_KEY: char = '\u{c}'; // Ctrl+L
+const SWAP_KEY: char = '\u{10}'; // Ctrl+P
+const REINDENT_KEY: char = '\u{12}'; // Ctrl+R
+const SAVE_KEY: char = '\u{13}'; // Ctrl+S
+const GO_TO_LINE_KEY: char = '\u{7}'; // Ctrl+G
+const UNDO_KEY: char = '\u{1a}'; // Ctrl+Z
+const REDO_KEY: char = '\u{19}'; // Ctrl+Y
+const SEARCH_KEY: char = '\u{17}'; // Ctrl+W
+const FIND_NEXT_KEY: char = '\u{e}'; // Ctrl+N
+const REPLACE_KEY: char = '\u{5}'; // Ctrl+E
+const CUT_LINE_KEY: char = '\u{18}'; // Ctrl+X
+const DUPLICATE_LINE_KEY: char = '\u{4}'; // Ctrl+D
+const ESCAPE_KEY: char = '\u{1b}';
+const DELETE_KEY: char = '\u{7f}';
 
 const MAX_OPEN: usize = 16;
 const BLOCK_SIZE: usize = 256;
@@ -41,9 +125,37 @@ const MAX_FILE_BYTES: usize = MAX_FILE_BLOCKS * BLOCK_SIZE;
 const MAX_FILES_STORED: usize = 30;
 const MAX_FILENAME_BYTES: usize = 10;
 
+const WINDOW_PALETTE: [Color; 5] = [Color::LightGreen, Color::LightCyan, Color::Yellow, Color::LightRed, Color::Pink];
+
+// Lines of output kept per process, for scrolling back with F7/F8.
+const OUTPUT_LINES: usize = 4 * WINDOW_HEIGHT;
+const OUTPUT_LINE_BYTES: usize = 3 * WINDOW_WIDTH;
+const PAN_STEP: usize = 8;
+
+// Files with a larger share of undrawable bytes than this aren't run.
+const MAX_BINARY_PERCENT: usize = 10;
+
+// Headless runs give up after this many instructions. They run inside the
+// key handler, with every other window and the clock stopped, so a program
+// that never ends mustn't hold things up for long.
+const MAX_HEADLESS_STEPS: usize = 20_000;
+const OUTPUT_FILE_SUFFIX: &[u8] = b".out";
+
+// Each scratch entry takes two rows, under which the input line sits.
+const SCRATCH_HISTORY: usize = (WINDOW_HEIGHT - 1) / 2;
+const SCRATCH_BINDINGS_BYTES: usize = 8 * WINDOW_WIDTH;
+const SCRATCH_PROMPT: &str = "> ";
+const INPUT_PROMPT: &str = "> ";
+const INPUT_CARET: char = '_';
+const SCRATCH_HEADER: &str = "(s) scratch";
+
+// VSFS can't write a file of exactly MAX_FILE_BYTES, so edit buffers stop
+// one byte short and anything saved from one always fits.
 const PRACTICAL_FILE_BUFFER_SIZE: usize = MAX_FILE_BYTES - 1;  // i made an oopsie in vsfs
 
 const MAX_TOKENS: usize = 500;
+
+const SYNTAX_KEYWORDS: [&[u8]; 8] = [b"while", b"if", b"else", b"not", b"true", b"false", b"input", b"print"];
 const MAX_LITERAL_CHARS: usize = 30;
 const STACK_DEPTH: usize = 50;
 const MAX_LOCAL_VARS: usize = 20;
@@ -77,6 +189,14 @@ impl KWindows {
             KWindows::F4 => MID_HEIGHT,
         }
     }
+    fn index(&self) -> usize {
+        match self {
+            KWindows::F1 => 0,
+            KWindows::F2 => 1,
+            KWindows::F3 => 2,
+            KWindows::F4 => 3,
+        }
+    }
     fn name(&self) -> &str {
         match self {
             KWindows::F1 => "F1",
@@ -87,20 +207,53 @@ impl KWindows {
     }
 }
 
+// The directory listing and every file's size, read once after each change
+// to the files instead of on every draw.
+#[derive(Clone, Copy, Debug)]
+struct DirectoryCache {
+    file_count: usize,
+    names: [[u8; MAX_FILENAME_BYTES]; MAX_FILES_STORED],
+    sizes: [usize; MAX_FILES_STORED],
+}
+
 #[derive(Clone, Copy, Debug)]
 struct DirectoryState {
     cursor: usize,
+    // The first row of files shown.
+    scroll: usize,
+    // The rows of files the window has room for.
+    height: usize,
 }
 
 impl DirectoryState {
+    fn new(cursor: usize) -> Self {
+        let mut dir_state = Self { cursor, scroll: 0, height: WINDOW_HEIGHT };
+        dir_state.scroll_to_cursor();
+        dir_state
+    }
+
     fn move_cursor(&mut self, delta: isize, file_count: usize) {
         let new_pos = self.cursor as isize + delta;
         if new_pos >= 0 && new_pos < file_count as isize {
             self.cursor = new_pos as usize;
         }
+        self.scroll_to_cursor();
+    }
+
+    // Scrolls by whole rows until the cursor's row is in the window.
+    fn scroll_to_cursor(&mut self) {
+        let cursor_row = self.cursor / DIRECTORY_COLUMNS;
+        if cursor_row < self.scroll {
+            self.scroll = cursor_row;
+        } else if cursor_row >= self.scroll + self.height {
+            self.scroll = cursor_row + 1 - self.height;
+        }
     }
 }
 
+// `buffer[..len]` is exactly what gets saved; every byte past `len` stays
+// zero. A file opened and saved without edits is written back byte for byte,
+// trailing newline included.
 #[derive(Clone, Copy, Debug)]
 struct EditingState {
     filename: [u8; MAX_FILENAME_BYTES],
@@ -109,231 +262,1405 @@ struct EditingState {
     cursor: usize,
     scroll: usize,
     directory_index: usize,
+    show_cursor_byte: bool,
+    save_review: Option<SaveReview>,
+    dirty: bool,
+    // Opening-brace positions of folded blocks. Folding only changes what is
+    // drawn; `contents` and saving always see the whole buffer.
+    folds: [usize; MAX_FOLDS],
+    fold_count: usize,
+    reindent_prompt: bool,
+    // Set when the file this was opened from is deleted, so saving under
+    // the same name doesn't silently overwrite a file created since.
+    detached: bool,
+    // The last text searched for, and where the match the cursor was moved
+    // to starts until the buffer next changes.
+    search: [u8; TEXT_PROMPT_BYTES],
+    search_len: usize,
+    found: Option<usize>,
+    // Oldest first: `undo_count` edits that can be undone, then `redo_count`
+    // that were.
+    edits: [Edit; UNDO_EDITS],
+    undo_count: usize,
+    redo_count: usize,
+    // The text area of the window showing this buffer, which lines wrap to.
+    width: usize,
+    height: usize,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum EditKind { Inserted, Removed }
+
+// One byte typed into or taken out of an edit buffer, and where the cursor
+// was beforehand. An edit `joined` to the one before is undone with it.
+#[derive(Clone, Copy, Debug)]
+struct Edit {
+    kind: EditKind,
+    index: usize,
+    byte: u8,
+    cursor: usize,
+    joined: bool,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum LineChange { Modified, Added, Removed }
+
+impl LineChange {
+    fn marker(&self) -> char {
+        match self {
+            LineChange::Modified => '~',
+            LineChange::Added => '+',
+            LineChange::Removed => '-',
+        }
+    }
+}
+
+// The logical lines that differ between a file on disk and its edit buffer,
+// compared position by position. Only the first `REVIEW_ENTRIES` are listed,
+// but all of them are counted.
+#[derive(Clone, Copy, Debug)]
+struct SaveReview {
+    entries: [(LineChange, usize); REVIEW_ENTRIES],
+    entry_count: usize,
+    counts: [usize; 3],
+}
+
+impl SaveReview {
+    fn compare(on_disk: &[u8], edited: &[u8]) -> Self {
+        let mut review = Self {
+            entries: [(LineChange::Modified, 0); REVIEW_ENTRIES],
+            entry_count: 0,
+            counts: [0; 3],
+        };
+        let mut old_lines = on_disk.split(|&byte| byte == b'\n');
+        let mut new_lines = edited.split(|&byte| byte == b'\n');
+        let mut line = 1;
+        loop {
+            let change = match (old_lines.next(), new_lines.next()) {
+                (None, None) => break,
+                (Some(old), Some(new)) if old == new => None,
+                (Some(_), Some(_)) => Some(LineChange::Modified),
+                (None, Some(_)) => Some(LineChange::Added),
+                (Some(_), None) => Some(LineChange::Removed),
+            };
+            if let Some(change) = change {
+                review.counts[change as usize] += 1;
+                if review.entry_count < REVIEW_ENTRIES {
+                    review.entries[review.entry_count] = (change, line);
+                    review.entry_count += 1;
+                }
+            }
+            line += 1;
+        }
+        review
+    }
 }
 
 impl EditingState {
+    fn contents(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+
     fn backspace(&mut self) {
         if self.cursor > 0 {
+            self.record(EditKind::Removed, self.cursor - 1, self.buffer[self.cursor - 1]);
             self.cursor -= 1;
-            self.buffer[self.cursor] = 0;
-            self.len -= 1;
+            self.remove_at(self.cursor);
+        }
+    }
+
+    // Deletes the byte under the cursor, leaving the cursor where it is.
+    fn delete(&mut self) {
+        if self.cursor < self.len {
+            self.record(EditKind::Removed, self.cursor, self.buffer[self.cursor]);
+            self.remove_at(self.cursor);
+        }
+    }
+
+    // Inserts `byte` at `index`, moving folds after it along with their
+    // braces.
+    fn insert_at(&mut self, index: usize, byte: u8) {
+        self.buffer.copy_within(index..self.len, index + 1);
+        self.buffer[index] = byte;
+        self.len += 1;
+        self.dirty = true;
+        self.found = None;
+        for fold in self.folds[..self.fold_count].iter_mut() {
+            if *fold >= index {
+                *fold += 1;
+            }
+        }
+    }
+
+    // Removes the byte at `index`, closing the gap and keeping folds on their
+    // braces. The fold whose brace it was is dropped.
+    fn remove_at(&mut self, index: usize) {
+        self.buffer.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+        self.buffer[self.len] = 0;
+        self.dirty = true;
+        self.found = None;
+        if let Some(fold) = self.folds[..self.fold_count].iter().position(|&fold| fold == index) {
+            self.folds.copy_within(fold + 1.., fold);
+            self.fold_count -= 1;
+        }
+        for fold in self.folds[..self.fold_count].iter_mut() {
+            if *fold > index {
+                *fold -= 1;
+            }
         }
     }
 
     fn type_char(&mut self, c: char) {
-        if self.cursor < PRACTICAL_FILE_BUFFER_SIZE {
-            self.buffer[self.cursor] = c as u8;
+        let Some(byte) = input_byte(c, true) else { return };
+        if self.len < PRACTICAL_FILE_BUFFER_SIZE {
+            self.record(EditKind::Inserted, self.cursor, byte);
+            self.insert_at(self.cursor, byte);
             self.cursor += 1;
-            self.len += 1;
         }
     }
 
-    fn line_count(&self, line_width: usize) -> usize {
-        let mut count = 1;
-        let mut cursor = 0;
-        let mut len = 0;
+    // Remembers an edit about to be made so it can be undone, and forgets
+    // anything undone before it. A run of typing, backspacing or deleting
+    // joins into one undo step; typing a newline starts a new one.
+    fn record(&mut self, kind: EditKind, index: usize, byte: u8) {
+        let joined = self.undo_count > 0 && {
+            let last = self.edits[self.undo_count - 1];
+            last.kind == kind && match kind {
+                EditKind::Inserted => index == last.index + 1 && byte != b'\n',
+                EditKind::Removed => index == last.index || index + 1 == last.index,
+            }
+        };
+        if self.undo_count == UNDO_EDITS {
+            self.edits.copy_within(1.., 0);
+            self.undo_count -= 1;
+        }
+        self.edits[self.undo_count] = Edit { kind, index, byte, cursor: self.cursor, joined };
+        self.undo_count += 1;
+        self.redo_count = 0;
+    }
+
+    // Edits that rewrite the whole buffer can't be replayed byte by byte, so
+    // they end the history instead.
+    fn forget_edits(&mut self) {
+        self.undo_count = 0;
+        self.redo_count = 0;
+        self.found = None;
+    }
+
+    // The logical line under the cursor, from its first byte up to its
+    // newline, or to the end of the buffer on the last line.
+    fn line_bounds(&self) -> (usize, usize) {
+        let contents = self.contents();
+        let start = contents[..self.cursor].iter().rposition(|&byte| byte == b'\n').map_or(0, |newline| newline + 1);
+        let end = contents[self.cursor..].iter().position(|&byte| byte == b'\n').map_or(self.len, |offset| self.cursor + offset);
+        (start, end)
+    }
+
+    // Removes the line under the cursor along with its newline, leaving the
+    // cursor at the start of the line that follows. Undoes in one step.
+    fn cut_line(&mut self) -> Result<(), &'static str> {
+        if self.len == 0 { return Err("nothing to cut") }
+        let (start, end) = self.line_bounds();
+        for _ in start..(end + 1).min(self.len) {
+            self.record(EditKind::Removed, start, self.buffer[start]);
+            self.remove_at(start);
+        }
+        self.cursor = start;
+        self.scroll_to_cursor();
+        Ok(())
+    }
+
+    // Copies the line under the cursor in below it and moves the cursor to
+    // the same column of the copy. Undoes in one step.
+    fn duplicate_line(&mut self) -> Result<(), &'static str> {
+        let (start, end) = self.line_bounds();
+        if self.len + end - start + 1 > PRACTICAL_FILE_BUFFER_SIZE { return Err("edit buffer full") }
+        // The newline goes first so the copy joins it into one undo step. The
+        // copy is inserted past the original, so reading from it is safe.
+        self.record(EditKind::Inserted, end, b'\n');
+        self.insert_at(end, b'\n');
+        for offset in 0..end - start {
+            let byte = self.buffer[start + offset];
+            self.record(EditKind::Inserted, end + 1 + offset, byte);
+            self.insert_at(end + 1 + offset, byte);
+        }
+        self.cursor += end - start + 1;
+        self.scroll_to_cursor();
+        Ok(())
+    }
+
+    // Replaces the first match of `old` at or after the cursor, wrapping
+    // around, or every match when `all` is set, leaving the cursor after the
+    // last replacement. Stops at the first replacement that wouldn't fit.
+    // Undo history is cleared, as with the other many-byte changes.
+    fn replace(&mut self, old: &[u8], new: &[u8], all: bool) -> Result<(), &'static str> {
+        let mut replaced = false;
+        let mut start = if all { 0 } else { self.cursor.min(self.len) };
         loop {
-            let &this_byte = match self.buffer.get(cursor) {
-                Some(byte) if byte == &0 => break,
-                Some(byte) => byte,
-                None => break,
-            };
-            if this_byte == '\n' as u8 {
-                count += 1;
-                cursor += 2;
-                len = 0;
-            } else if len == line_width {
-                count += 1;
-                cursor += 1;
-                len = 0;
-            } else {
-                cursor += 1;
-                len += 1;
+            let contents = self.contents();
+            let wrap = if all { 0..0 } else { 0..start };
+            let Some(found) = (start..contents.len())
+                .chain(wrap)
+                .find(|&index| contents[index..].starts_with(old)) else { break };
+            if self.len - old.len() + new.len() > PRACTICAL_FILE_BUFFER_SIZE {
+                if replaced { break }
+                return Err("edit buffer full");
+            }
+            for _ in 0..old.len() {
+                self.remove_at(found);
+            }
+            for (i, &byte) in new.iter().enumerate() {
+                self.insert_at(found + i, byte);
             }
+            replaced = true;
+            start = found + new.len();
+            self.cursor = start;
+            if !all { break }
         }
-        count
+        if !replaced { return Err("not found") }
+        self.forget_edits();
+        self.scroll_to_cursor();
+        Ok(())
     }
 
-    fn read_line(&self, line: usize) -> Option<[u8; WINDOW_WIDTH]> {
-        let mut line_buf = [' ' as u8; WINDOW_WIDTH];
-        let mut current_line = 0;
-        let mut line_start = 0;
-        let mut line_len = 0;
+    // Moves the cursor to the first match of the search text at or after
+    // `start`, wrapping around to the top, and remembers it to highlight.
+    fn find(&mut self, start: usize) -> bool {
+        let needle = &self.search[..self.search_len];
+        let contents = self.contents();
+        let start = start.min(contents.len());
+        self.found = (start..contents.len())
+            .chain(0..start)
+            .find(|&index| contents[index..].starts_with(needle));
+        if let Some(found) = self.found {
+            self.cursor = found;
+            self.scroll_to_cursor();
+        }
+        self.found.is_some()
+    }
+
+    fn undo(&mut self) -> Result<(), &'static str> {
+        if self.undo_count == 0 { return Err("nothing to undo") }
         loop {
-            if current_line > line { break }
-            let &this_byte = match self.buffer.get(line_start + line_len) {
-                Some(byte) => byte,
-                None => break,
-            };
-            if this_byte == '\n' as u8 {
-                current_line += 1;
-                line_start += line_len + 1;
-                line_len = 0;
-            } else if line_len == WINDOW_WIDTH {
-                current_line += 1;
-                line_start += line_len;
-                line_len = 0;
-            } else {
-                if current_line == line {
-                    line_buf[line_len] = this_byte;
-                }
-                line_len += 1;
+            self.undo_count -= 1;
+            self.redo_count += 1;
+            let edit = self.edits[self.undo_count];
+            match edit.kind {
+                EditKind::Inserted => self.remove_at(edit.index),
+                EditKind::Removed => self.insert_at(edit.index, edit.byte),
+            }
+            self.cursor = edit.cursor;
+            if !edit.joined || self.undo_count == 0 { break }
+        }
+        self.scroll_to_cursor();
+        Ok(())
+    }
+
+    fn redo(&mut self) -> Result<(), &'static str> {
+        if self.redo_count == 0 { return Err("nothing to redo") }
+        loop {
+            let edit = self.edits[self.undo_count];
+            match edit.kind {
+                EditKind::Inserted => {
+                    self.insert_at(edit.index, edit.byte);
+                    self.cursor = edit.index + 1;
+                },
+                EditKind::Removed => {
+                    self.remove_at(edit.index);
+                    self.cursor = edit.index;
+                },
+            }
+            self.undo_count += 1;
+            self.redo_count -= 1;
+            if self.redo_count == 0 || !self.edits[self.undo_count].joined { break }
+        }
+        self.scroll_to_cursor();
+        Ok(())
+    }
+
+    // Drops everything from the cursor on, leaving the scroll on a line that
+    // still exists.
+    fn truncate_at_cursor(&mut self) {
+        self.buffer[self.cursor..self.len].fill(0);
+        self.len = self.cursor;
+        self.dirty = true;
+        self.forget_edits();
+        self.scroll = self.scroll.min(self.line_count(self.width) - 1);
+    }
+
+    // Rewrites the leading spaces of every line from the buffer's indent width
+    // to `width`. The buffer's width is taken to be its smallest indent; any
+    // spaces past a whole number of levels are kept as they are.
+    fn reindent(&mut self, width: usize) -> Result<(), &'static str> {
+        let contents = self.contents();
+        let indent_of = |line: &[u8]| line.iter().take_while(|&&byte| byte == b' ').count();
+        let Some(current) = contents.split(|&byte| byte == b'\n').map(indent_of).filter(|&indent| indent > 0).min() else {
+            return Err("no indented lines");
+        };
+        let mut reindented = [0u8; PRACTICAL_FILE_BUFFER_SIZE];
+        let mut len = 0;
+        let mut cursor = None;
+        let mut start = 0;
+        for line in contents.split(|&byte| byte == b'\n') {
+            let indent = indent_of(line);
+            let new_indent = indent / current * width + indent % current;
+            let end = (start + line.len() + 1).min(contents.len());
+            let rest = &contents[start + indent..end];
+            if len + new_indent + rest.len() > PRACTICAL_FILE_BUFFER_SIZE {
+                return Err("reindented file wouldn't fit");
+            }
+            if (start..end).contains(&self.cursor) {
+                let offset = self.cursor - start;
+                cursor = Some(len + if offset < indent { offset.min(new_indent) } else { new_indent + offset - indent });
             }
+            reindented[len..len + new_indent].fill(b' ');
+            len += new_indent;
+            reindented[len..len + rest.len()].copy_from_slice(rest);
+            len += rest.len();
+            start = end;
+        }
+        self.buffer = reindented;
+        self.cursor = cursor.unwrap_or(len);
+        self.len = len;
+        self.fold_count = 0;
+        self.forget_edits();
+        self.dirty = true;
+        self.scroll = self.scroll.min(self.line_count(self.width) - 1);
+        Ok(())
+    }
+
+    // Types `value` in decimal at the cursor, or nothing if it won't all fit.
+    fn insert_number(&mut self, value: usize) {
+        let digit_count = num_digits(value);
+        if self.len + digit_count > PRACTICAL_FILE_BUFFER_SIZE { return }
+        let mut digits = [0u8; 20];
+        let mut rest = value;
+        for digit in digits[..digit_count].iter_mut().rev() {
+            *digit = b'0' + (rest % 10) as u8;
+            rest /= 10;
+        }
+        for &digit in &digits[..digit_count] {
+            self.type_char(digit as char);
+        }
+    }
+
+    fn byte_at_cursor(&self) -> Option<u8> {
+        if self.cursor < self.len {
+            Some(self.buffer[self.cursor])
+        } else {
+            None
         }
+    }
+
+    fn line_count(&self, line_width: usize) -> usize {
+        self.walk_display(line_width, |_, _, _, _| {})
+    }
+
+    // Only the first `width` bytes of the line are ever set.
+    fn read_line(&self, line: usize) -> Option<[u8; MAX_WINDOW_WIDTH]> {
+        let mut line_buf = [' ' as u8; MAX_WINDOW_WIDTH];
+        let line_count = self.walk_display(self.width, |this_line, col, _, byte| {
+            if this_line == line && byte != b'\n' {
+                line_buf[col] = byte;
+            }
+        });
 
-        if current_line > line {
+        // A line with no bytes still counts: the last line is empty right
+        // after a trailing newline.
+        if line < line_count {
             Some(line_buf)
         } else {
             None
         }
     }
-}
 
-#[derive(Clone, Copy, Debug)]
-struct RunningState {
-    interpreter: Interpreter<
-        MAX_TOKENS,
-        MAX_LITERAL_CHARS,
-        STACK_DEPTH,
-        MAX_LOCAL_VARS,
-        WINDOW_WIDTH,
-        DummyHeap<HEAP_SIZE, MAX_HEAP_BLOCKS>,
-    >,
-}
+    // Lays the displayed text out in lines of `line_width`, calling `visit`
+    // with the line, column, buffer index, and value of every byte shown, and
+    // of each newline where it ends its line. Returns the number of lines.
+    fn walk_display(&self, line_width: usize, mut visit: impl FnMut(usize, usize, usize, u8)) -> usize {
+        let mut line = 0;
+        let mut col = 0;
+        for (index, byte) in self.display_bytes() {
+            if byte == '\n' as u8 {
+                visit(line, col, index, byte);
+                line += 1;
+                col = 0;
+            } else {
+                if col == line_width {
+                    // The byte that didn't fit starts the next line.
+                    line += 1;
+                    col = 0;
+                }
+                visit(line, col, index, byte);
+                col += 1;
+            }
+        }
+        line + 1
+    }
 
-// dummy struct, allows interpreter to compile
-#[derive(Clone, Copy, Debug)]
-struct DummyHeap<const HEAP_SIZE: usize, const MAX_HEAP_BLOCKS: usize>;
-impl GarbageCollectingHeap for DummyHeap<HEAP_SIZE, MAX_HEAP_BLOCKS> {
-    fn new() -> Self {todo!("dummy heap")}
-    fn load(&self, p: gc_headers::Pointer) -> gc_headers::HeapResult<u64> {todo!("dummy heap")}
-    fn store(&mut self, p: gc_headers::Pointer, value: u64) -> gc_headers::HeapResult<()> {todo!("dummy heap")}
-    fn malloc<T: gc_headers::Tracer>(&mut self, num_words: usize, tracer: &T) -> gc_headers::HeapResult<gc_headers::Pointer> {todo!("dummy heap")}
-}
+    // The line and column the cursor is drawn at: on the byte it is before,
+    // or just past the last byte when it is at the end. Past the end of a
+    // full line is the start of the next, where the next byte typed goes.
+    fn cursor_position(&self) -> (usize, usize) {
+        let mut position = None;
+        let mut end = (0, 0);
+        self.walk_display(self.width, |line, col, index, byte| {
+            if index >= self.cursor && position.is_none() {
+                position = Some((line, col));
+            }
+            end = if byte == b'\n' { (line + 1, 0) } else { (line, col + 1) };
+        });
+        match position.unwrap_or(end) {
+            (line, col) if col == self.width => (line + 1, 0),
+            position => position,
+        }
+    }
 
-#[derive(Clone, Copy, Debug)]
-enum KWindowMode {
-    Directory(DirectoryState),
-    Editing(EditingState),
-    Running(RunningState),
-}
+    // The cursor position on `line` nearest `col` without passing it, or None
+    // past the last line. Short lines put the cursor at their end.
+    fn index_at(&self, line: usize, col: usize) -> Option<usize> {
+        let mut found = None;
+        let line_count = self.walk_display(self.width, |this_line, this_col, index, byte| {
+            if this_line == line && this_col <= col {
+                found = Some(if this_col < col && byte != b'\n' { index + 1 } else { index });
+            }
+        });
+        if line < line_count {
+            Some(found.unwrap_or(self.len))
+        } else {
+            None
+        }
+    }
 
-impl KWindowMode {
-    fn directory(cursor: usize) -> Self {
-        Self::Directory(DirectoryState { cursor })
+    // Moves the cursor a byte left or right, a displayed line up or down, or
+    // to either end of its displayed line, stepping over folded blocks, then
+    // scrolls to keep it in the window. The end of a wrapped line is its last
+    // column, since the cursor can't sit past it without starting the next.
+    fn move_cursor(&mut self, direction: Direction) {
+        let (line, col) = self.cursor_position();
+        self.cursor = match direction {
+            Direction::Left => {
+                let left = self.cursor.saturating_sub(1);
+                self.folds[..self.fold_count].iter()
+                    .copied()
+                    .find(|&open| self.folded_block_end(open).is_some_and(|close| open < left && left < close))
+                    .unwrap_or(left)
+            },
+            Direction::Right => self.folded_block_end(self.cursor).unwrap_or((self.cursor + 1).min(self.len)),
+            Direction::Up if line == 0 => 0,
+            Direction::Up => self.index_at(line - 1, col).unwrap(),
+            Direction::Down => self.index_at(line + 1, col).unwrap_or(self.len),
+            Direction::LineStart => self.index_at(line, 0).unwrap_or(self.len),
+            Direction::LineEnd => self.index_at(line, self.width - 1).unwrap_or(self.len),
+        };
+        self.scroll_to_cursor();
     }
 
-    fn editing(
-        filename: [u8; MAX_FILENAME_BYTES],
-        buffer: [u8; PRACTICAL_FILE_BUFFER_SIZE],
-        len: usize,
-        directory_index: usize,
-    ) -> Self {
-        let mut state = EditingState {
-            filename,
-            buffer,
-            len,
-            cursor: len,
-            scroll: 0,
-            directory_index,
+    fn scroll_to_cursor(&mut self) {
+        let (line, _) = self.cursor_position();
+        if line < self.scroll {
+            self.scroll = line;
+        } else if line >= self.scroll + self.height {
+            self.scroll = line + 1 - self.height;
+        }
+    }
+
+    // Puts the cursor at the start of logical line `line`, counting from 1,
+    // with that line at the top of the window. Returns false past the end.
+    fn go_to_line(&mut self, line: usize) -> bool {
+        let start = match line {
+            0 => return false,
+            1 => 0,
+            _ => match self.contents().iter().enumerate().filter(|&(_, &byte)| byte == b'\n').nth(line - 2) {
+                Some((newline, _)) => newline + 1,
+                None => return false,
+            },
         };
-        state.scroll = state.line_count(WINDOW_WIDTH).saturating_sub(WINDOW_HEIGHT);
-        Self::Editing(state)
+        self.cursor = start;
+        self.scroll = self.cursor_position().0;
+        true
     }
 
-    fn running(program: &str) -> Self {
-        Self::Running(RunningState {
-            interpreter: Interpreter::new(program)
-        })
+    fn display_bytes(&self) -> DisplayBytes<'_> {
+        DisplayBytes { edit_state: self, index: 0, placeholder: None }
     }
-}
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
-enum KSelection { Window(KWindows), Filebar }
+    // Finds the `}` closing the block opened at `open`, if it is closed.
+    fn matching_brace(&self, open: usize) -> Option<usize> {
+        let mut depth = 0;
+        for (index, &byte) in self.contents().iter().enumerate().skip(open) {
+            match byte {
+                b'{' => depth += 1,
+                b'}' if depth == 1 => return Some(index),
+                b'}' => depth -= 1,
+                _ => {},
+            }
+        }
+        None
+    }
 
-struct TypingBuffer<const MAX_LENGTH: usize> {
-    buffer: [u8; MAX_LENGTH],
-    cursor: usize,
+    // Where the folded block opened at `open` ends. A fold whose brace was
+    // erased, or that is no longer closed, hides nothing.
+    fn folded_block_end(&self, open: usize) -> Option<usize> {
+        if !self.folds[..self.fold_count].contains(&open) { return None }
+        if self.contents().get(open) != Some(&b'{') { return None }
+        self.matching_brace(open)
+    }
+
+    // Folds the first block opened on the cursor's displayed line, or unfolds
+    // it if it is already folded.
+    fn toggle_fold(&mut self) -> Result<(), &'static str> {
+        let (cursor_line, _) = self.cursor_position();
+        let mut open = None;
+        self.walk_display(self.width, |line, _, index, byte| {
+            if line == cursor_line && byte == b'{' && open.is_none() {
+                open = Some(index);
+            }
+        });
+        let Some(open) = open else { return Err("no block opens on this line") };
+        if let Some(fold) = self.folds[..self.fold_count].iter().position(|&fold| fold == open) {
+            self.folds.copy_within(fold + 1.., fold);
+            self.fold_count -= 1;
+            return Ok(());
+        }
+        if self.matching_brace(open).is_none() { return Err("block isn't closed") }
+        if self.fold_count == MAX_FOLDS { return Err("too many folds") }
+        self.folds[self.fold_count] = open;
+        self.fold_count += 1;
+        Ok(())
+    }
 }
 
-impl TypingBuffer<MAX_FILENAME_BYTES> {
-    fn type_char(&mut self, c: char) {
-        if self.cursor < MAX_FILENAME_BYTES {
-            self.buffer[self.cursor] = c as u8;
-            self.cursor += 1;
+// The bytes an editing window shows, paired with where in the buffer each one
+// came from. A folded block's contents are replaced by `FOLD_PLACEHOLDER`,
+// which is attributed to the block's opening brace.
+struct DisplayBytes<'a> {
+    edit_state: &'a EditingState,
+    index: usize,
+    placeholder: Option<(usize, usize)>,
+}
+
+impl Iterator for DisplayBytes<'_> {
+    type Item = (usize, u8);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((open, shown)) = self.placeholder {
+            if shown < FOLD_PLACEHOLDER.len() {
+                self.placeholder = Some((open, shown + 1));
+                return Some((open, FOLD_PLACEHOLDER[shown]));
+            }
+            self.placeholder = None;
+        }
+        let index = self.index;
+        let &byte = self.edit_state.contents().get(index)?;
+        match self.edit_state.folded_block_end(index) {
+            Some(close) => {
+                self.index = close;
+                self.placeholder = Some((index, 0));
+            },
+            None => self.index += 1,
         }
+        Some((index, byte))
     }
+}
 
-    fn backspace(&mut self) {
-        if self.cursor > 0 {
-            self.buffer[self.cursor - 1] = 0;
-            self.cursor -= 1;
+// Interpreter output for one window, as logical lines of up to
+// `OUTPUT_LINE_BYTES` that are wrapped or panned when drawn. Only the most
+// recent `OUTPUT_LINES` lines are kept, each stamped with the tick on which it
+// was started.
+#[derive(Clone, Copy, Debug)]
+struct WindowOutput {
+    lines: [[u8; OUTPUT_LINE_BYTES]; OUTPUT_LINES],
+    lens: [usize; OUTPUT_LINES],
+    ticks: [usize; OUTPUT_LINES],
+    count: usize,
+    line_open: bool,
+    now: usize,
+    printed: bool,
+    lines_started: usize,
+}
+
+impl WindowOutput {
+    fn new() -> Self {
+        Self {
+            lines: [[0; OUTPUT_LINE_BYTES]; OUTPUT_LINES],
+            lens: [0; OUTPUT_LINES],
+            ticks: [0; OUTPUT_LINES],
+            count: 0,
+            line_open: false,
+            now: 0,
+            printed: false,
+            lines_started: 0,
         }
     }
 
-    fn clear(&mut self) {
-        self.buffer = [0; MAX_FILENAME_BYTES];
-        self.cursor = 0;
+    fn start_line(&mut self) {
+        if self.count == OUTPUT_LINES {
+            self.lines.copy_within(1.., 0);
+            self.lens.copy_within(1.., 0);
+            self.ticks.copy_within(1.., 0);
+            self.count -= 1;
+        }
+        self.lens[self.count] = 0;
+        self.ticks[self.count] = self.now;
+        self.count += 1;
+        self.line_open = true;
+        self.lines_started += 1;
     }
 
-    fn draw(&self, col: usize, row: usize, color: ColorCode) {
-        for i in 0..MAX_FILENAME_BYTES {
-            let char_to_plot = if i < self.cursor { self.buffer[i] as char } else { ' ' };
-            plot(char_to_plot, col + i, row, color);
+    fn push_byte(&mut self, byte: u8) {
+        if byte == b'\n' {
+            if !self.line_open {
+                self.start_line();
+            }
+            self.line_open = false;
+            return;
+        }
+        if !self.line_open || self.lens[self.count - 1] == OUTPUT_LINE_BYTES {
+            self.start_line();
         }
+        let last = self.count - 1;
+        self.lines[last][self.lens[last]] = byte;
+        self.lens[last] += 1;
     }
 
-    fn get_bytes(&mut self) -> (usize, [u8; MAX_FILENAME_BYTES]) {
-        (self.cursor, self.buffer.clone())
+    fn line(&self, index: usize) -> &[u8] {
+        &self.lines[index][..self.lens[index]]
     }
 }
 
-pub struct Kernel {
-    selected: KSelection,
-    filebar_buffer: TypingBuffer<MAX_FILENAME_BYTES>,
-    window_modes: [KWindowMode; 4],
-    fs: FileSystem<
-        MAX_OPEN, 
-        BLOCK_SIZE, 
-        NUM_BLOCKS, 
-        MAX_FILE_BLOCKS, 
-        MAX_FILE_BYTES, 
-        MAX_FILES_STORED, 
-        MAX_FILENAME_BYTES
-    >,
+impl InterpreterOutput for WindowOutput {
+    fn print(&mut self, chars: &[u8]) {
+        self.printed = true;
+        for &byte in chars {
+            self.push_byte(byte);
+        }
+    }
 }
 
-const HELLO: &str = r#"print("Hello, world!")"#;
-
-const NUMS: &str = r#"print(1)
-print(257)"#;
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum ProcessStatus { Runnable, AwaitingInput, Finished }
 
-const ADD_ONE: &str = r#"x := input("Enter a number")
-x := (x + 1)
-print(x)"#;
+type ProgramInterpreter = Interpreter<
+    MAX_TOKENS,
+    MAX_LITERAL_CHARS,
+    STACK_DEPTH,
+    MAX_LOCAL_VARS,
+    WINDOW_WIDTH,
+    CopyingHeap<HEAP_SIZE, MAX_HEAP_BLOCKS>,
+>;
 
-const COUNTDOWN: &str = r#"count := input("count")
-while (count > 0) {
-    count := (count - 1)
+#[derive(Clone, Copy, Debug)]
+struct RunningState {
+    interpreter: ProgramInterpreter,
+    filename: [u8; MAX_FILENAME_BYTES],
+    // The source the run started from, so a rerun doesn't need the file,
+    // which may since have been edited, renamed or deleted.
+    program: [u8; PRACTICAL_FILE_BUFFER_SIZE],
+    program_len: usize,
+    output: WindowOutput,
+    status: ProcessStatus,
+    paused: bool,
+    step_mode: bool,
+    // Pause after every instruction that prints or asks for input, so the
+    // program can be followed one piece of output at a time.
+    pause_on_output: bool,
+    show_ticks: bool,
+    wrap_output: bool,
+    hscroll: usize,
+    // Output lines scrolled back from the newest; 0 follows new output.
+    scroll: usize,
+    pager: bool,
+    more_prompt: bool,
+    acked_lines: usize,
+    // The output rows the window shows, which the pager pages by.
+    height: usize,
+    instructions_run: usize,
+    // The line being typed for a program waiting in `input()`, and whether
+    // that `input()` printed a prompt, which is then the last output line.
+    input: [u8; WINDOW_WIDTH],
+    input_len: usize,
+    prompted: bool,
+    error: Option<ErrorText>,
 }
-print("done")
-print(count)"#;
 
-const AVERAGE: &str = r#"sum := 0
-count := 0
-averaging := true
-while averaging {
-    num := input("Enter a number:")
-    if (num == "quit") {
-        averaging := false
-    } else {
-        sum := (sum + num)
-        count := (count + 1)
-    }
+// What a window keeps of a run it has left: enough to show its output and
+// footer again, but not the interpreter.
+#[derive(Clone, Copy, Debug)]
+struct LastRun {
+    filename: [u8; MAX_FILENAME_BYTES],
+    program: [u8; PRACTICAL_FILE_BUFFER_SIZE],
+    program_len: usize,
+    output: WindowOutput,
+    instructions_run: usize,
+    error: Option<ErrorText>,
 }
-print((sum / count))"#;
 
-const PI: &str = r#"sum := 0
+// An interpreter error, formatted to fit on one row of a window.
+#[derive(Clone, Copy, Debug)]
+struct ErrorText {
+    bytes: [u8; WINDOW_WIDTH],
+    len: usize,
+}
+
+impl fmt::Write for ErrorText {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        for &byte in text.as_bytes() {
+            if self.len < WINDOW_WIDTH {
+                self.bytes[self.len] = byte;
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RunningState {
+    // Executes a single interpreter instruction, stamping any output with
+    // `now`. Returns whether the instruction printed anything.
+    fn step(&mut self, now: usize) -> bool {
+        if self.status != ProcessStatus::Runnable || self.more_prompt { return false }
+        self.output.now = now;
+        self.output.printed = false;
+        self.instructions_run += 1;
+        let lines_started = self.output.lines_started;
+        match self.interpreter.tick(&mut self.output) {
+            Ok(TickStatus::Continuing) => {},
+            Ok(TickStatus::AwaitInput) => {
+                self.status = ProcessStatus::AwaitingInput;
+                self.prompted = self.output.printed;
+            },
+            Ok(TickStatus::Finished) => self.status = ProcessStatus::Finished,
+            Err(error) => self.halt_with_error(error),
+        }
+        // A scrolled-back view stays on the lines it was showing.
+        if self.scroll > 0 {
+            self.scroll_by((self.output.lines_started - lines_started) as isize);
+        }
+        if self.pager && self.output.lines_started - self.acked_lines >= self.height {
+            self.more_prompt = true;
+        }
+        if self.pause_on_output && (self.output.printed || self.status == ProcessStatus::AwaitingInput) {
+            self.paused = true;
+        }
+        self.output.printed
+    }
+
+    // Starts the program over with a new interpreter, so an empty heap, no
+    // variables, and no output.
+    fn rerun(&mut self) {
+        self.interpreter = Interpreter::new(str::from_utf8(&self.program[..self.program_len]).unwrap());
+        self.output = WindowOutput::new();
+        self.status = ProcessStatus::Runnable;
+        self.paused = self.step_mode || self.pause_on_output;
+        self.more_prompt = false;
+        self.acked_lines = 0;
+        self.instructions_run = 0;
+        self.input_len = 0;
+        self.scroll = 0;
+        self.error = None;
+    }
+
+    fn type_char(&mut self, c: char) {
+        let Some(byte) = input_byte(c, false) else { return };
+        if self.input_len < WINDOW_WIDTH {
+            self.input[self.input_len] = byte;
+            self.input_len += 1;
+        }
+    }
+
+    fn backspace(&mut self) {
+        self.input_len = self.input_len.saturating_sub(1);
+    }
+
+    // Hands the typed line to the waiting `input()`, echoing it into the
+    // output so the transcript shows what was entered.
+    fn submit_input(&mut self) {
+        let line = &self.input[..self.input_len];
+        for &byte in line {
+            self.output.push_byte(byte);
+        }
+        self.output.push_byte(b'\n');
+        match self.interpreter.provide_input(line) {
+            Ok(()) => self.status = ProcessStatus::Runnable,
+            Err(error) => self.halt_with_error(error),
+        }
+        self.input_len = 0;
+    }
+
+    // Stops the program for good, keeping the interpreter's error to show
+    // under its output.
+    fn halt_with_error(&mut self, error: impl fmt::Debug) {
+        let mut text = ErrorText { bytes: [0; WINDOW_WIDTH], len: 0 };
+        // `ErrorText` cuts long messages short rather than failing.
+        let _ = write!(text, "error: {:?}", error);
+        self.error = Some(text);
+        self.status = ProcessStatus::Finished;
+    }
+
+    // Positive `delta` scrolls back toward older output.
+    fn scroll_by(&mut self, delta: isize) {
+        self.scroll = self.scroll.saturating_add_signed(delta).min(self.output.count.saturating_sub(1));
+    }
+
+    fn pan(&mut self, delta: isize) {
+        let offset = self.hscroll.saturating_add_signed(delta * PAN_STEP as isize);
+        self.hscroll = offset.min(OUTPUT_LINE_BYTES - 1);
+    }
+
+    // Dismisses the pager's "-- more --" prompt, letting another screenful of
+    // output through.
+    fn acknowledge_output(&mut self) {
+        self.more_prompt = false;
+        self.acked_lines = self.output.lines_started;
+    }
+}
+
+// Output from a headless run, collected for writing to a file, and how many
+// instructions the run took. Output past what a file can hold is dropped,
+// and `truncated` records that some was.
+struct CapturedOutput {
+    bytes: [u8; PRACTICAL_FILE_BUFFER_SIZE],
+    len: usize,
+    steps: usize,
+    truncated: bool,
+}
+
+impl InterpreterOutput for CapturedOutput {
+    fn print(&mut self, chars: &[u8]) {
+        for &byte in chars {
+            if self.len < PRACTICAL_FILE_BUFFER_SIZE {
+                self.bytes[self.len] = byte;
+                self.len += 1;
+            } else {
+                self.truncated = true;
+            }
+        }
+    }
+}
+
+// Runs `program` to completion without a window, answering each `input()`
+// with the next of `inputs`. A program that asks for more input than it was
+// given fails instead of hanging.
+fn run_headless(program: &str, inputs: &[&str]) -> Result<CapturedOutput, &'static str> {
+    let mut interpreter = ProgramInterpreter::new(program);
+    let mut output = CapturedOutput { bytes: [0; PRACTICAL_FILE_BUFFER_SIZE], len: 0, steps: 0, truncated: false };
+    let mut inputs = inputs.iter();
+    for _ in 0..MAX_HEADLESS_STEPS {
+        output.steps += 1;
+        match interpreter.tick(&mut output) {
+            Ok(TickStatus::Continuing) => {},
+            Ok(TickStatus::Finished) => return Ok(output),
+            Ok(TickStatus::AwaitInput) => {
+                let Some(input) = inputs.next() else { return Err("program needs input") };
+                if interpreter.provide_input(input.as_bytes()).is_err() {
+                    return Err("program failed");
+                }
+            },
+            Err(_) => return Err("program failed"),
+        }
+    }
+    Err("program didn't finish")
+}
+
+// One line typed into a scratch window and the first line of what it printed,
+// or why it couldn't run.
+#[derive(Clone, Copy, Debug)]
+struct ScratchEntry {
+    line: [u8; WINDOW_WIDTH],
+    line_len: usize,
+    result: [u8; WINDOW_WIDTH],
+    result_len: usize,
+}
+
+// A REPL-style window. Each line is run headlessly as a program of its own,
+// after the assignments that worked so far, so variables carry over between
+// lines. A line that is neither a `print` nor an assignment is treated as an
+// expression and printed.
+#[derive(Clone, Copy, Debug)]
+struct ScratchState {
+    input: [u8; WINDOW_WIDTH],
+    input_len: usize,
+    history: [ScratchEntry; SCRATCH_HISTORY],
+    history_count: usize,
+    bindings: [u8; SCRATCH_BINDINGS_BYTES],
+    bindings_len: usize,
+}
+
+impl ScratchState {
+    fn new() -> Self {
+        let entry = ScratchEntry { line: [0; WINDOW_WIDTH], line_len: 0, result: [0; WINDOW_WIDTH], result_len: 0 };
+        Self {
+            input: [0; WINDOW_WIDTH],
+            input_len: 0,
+            history: [entry; SCRATCH_HISTORY],
+            history_count: 0,
+            bindings: [0; SCRATCH_BINDINGS_BYTES],
+            bindings_len: 0,
+        }
+    }
+
+    fn type_char(&mut self, c: char) {
+        let Some(byte) = input_byte(c, false) else { return };
+        if self.input_len < WINDOW_WIDTH {
+            self.input[self.input_len] = byte;
+            self.input_len += 1;
+        }
+    }
+
+    fn backspace(&mut self) {
+        if self.input_len > 0 {
+            self.input_len -= 1;
+            self.input[self.input_len] = 0;
+        }
+    }
+
+    // Runs the typed line and moves it into the history with its result.
+    fn evaluate(&mut self) {
+        if self.input_len == 0 { return }
+        let line = &self.input[..self.input_len];
+        let is_assignment = line.windows(2).any(|pair| pair == b":=");
+        let is_statement = is_assignment || line.starts_with(b"print(");
+        let mut program = [0u8; SCRATCH_BINDINGS_BYTES + WINDOW_WIDTH + 8];
+        let mut len = self.bindings_len;
+        program[..len].copy_from_slice(&self.bindings[..len]);
+        for part in [if is_statement { b"" as &[u8] } else { b"print(" }, line, if is_statement { b"" } else { b")" }] {
+            program[len..len + part.len()].copy_from_slice(part);
+            len += part.len();
+        }
+        let mut entry = ScratchEntry { line: self.input, line_len: self.input_len, result: [0; WINDOW_WIDTH], result_len: 0 };
+        let outcome = match str::from_utf8(&program[..len]) {
+            Ok(program) => run_headless(program, &[]),
+            Err(_) => Err("not text"),
+        };
+        let result = match &outcome {
+            Ok(output) => output.bytes[..output.len].split(|&byte| byte == b'\n').next().unwrap_or(b""),
+            Err(message) => message.as_bytes(),
+        };
+        entry.result_len = result.len().min(WINDOW_WIDTH);
+        entry.result[..entry.result_len].copy_from_slice(&result[..entry.result_len]);
+        if outcome.is_ok() && is_assignment && self.bindings_len + self.input_len < SCRATCH_BINDINGS_BYTES {
+            self.bindings[self.bindings_len..self.bindings_len + self.input_len].copy_from_slice(line);
+            self.bindings[self.bindings_len + self.input_len] = b'\n';
+            self.bindings_len += self.input_len + 1;
+        }
+        if self.history_count == SCRATCH_HISTORY {
+            self.history.copy_within(1.., 0);
+            self.history_count -= 1;
+        }
+        self.history[self.history_count] = entry;
+        self.history_count += 1;
+        self.input = [0; WINDOW_WIDTH];
+        self.input_len = 0;
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum KWindowMode {
+    Directory(DirectoryState),
+    Editing(EditingState),
+    Running(RunningState),
+    SysInfo,
+    Scratch(ScratchState),
+}
+
+impl KWindowMode {
+    fn directory(cursor: usize) -> Self {
+        Self::Directory(DirectoryState::new(cursor))
+    }
+
+    fn editing(
+        filename: [u8; MAX_FILENAME_BYTES],
+        buffer: [u8; PRACTICAL_FILE_BUFFER_SIZE],
+        len: usize,
+        directory_index: usize,
+    ) -> Self {
+        let mut state = EditingState {
+            filename,
+            buffer,
+            len,
+            cursor: len,
+            scroll: 0,
+            directory_index,
+            show_cursor_byte: false,
+            save_review: None,
+            dirty: false,
+            folds: [0; MAX_FOLDS],
+            fold_count: 0,
+            reindent_prompt: false,
+            edits: [Edit { kind: EditKind::Inserted, index: 0, byte: 0, cursor: 0, joined: false }; UNDO_EDITS],
+            undo_count: 0,
+            redo_count: 0,
+            detached: false,
+            search: [0; TEXT_PROMPT_BYTES],
+            search_len: 0,
+            found: None,
+            width: WINDOW_WIDTH,
+            height: WINDOW_HEIGHT,
+        };
+        state.scroll = state.line_count(state.width).saturating_sub(state.height);
+        Self::Editing(state)
+    }
+
+    // A program started in step mode sits paused before its first
+    // instruction until it is stepped.
+    fn running(filename: [u8; MAX_FILENAME_BYTES], program: &str, step_mode: bool) -> Self {
+        let interpreter = Interpreter::new(program);
+        let mut program_bytes = [0; PRACTICAL_FILE_BUFFER_SIZE];
+        program_bytes[..program.len()].copy_from_slice(program.as_bytes());
+        Self::Running(RunningState {
+            interpreter,
+            filename,
+            program: program_bytes,
+            program_len: program.len(),
+            output: WindowOutput::new(),
+            status: ProcessStatus::Runnable,
+            paused: step_mode,
+            step_mode,
+            pause_on_output: false,
+            show_ticks: false,
+            wrap_output: true,
+            hscroll: 0,
+            scroll: 0,
+            pager: false,
+            more_prompt: false,
+            acked_lines: 0,
+            height: WINDOW_HEIGHT,
+            instructions_run: 0,
+            input: [0; WINDOW_WIDTH],
+            input_len: 0,
+            prompted: false,
+            error: None,
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum KSelection { Window(KWindows), Filebar }
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum Direction { Up, Down, Left, Right, LineStart, LineEnd }
+
+// Where a window is drawn: the top left of its border, and the size of the
+// area inside it.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+struct Frame { col: usize, row: usize, width: usize, height: usize }
+
+impl Frame {
+    fn label_col_offset(&self) -> usize {
+        self.width - 3
+    }
+}
+
+// A command the kernel can carry out on behalf of a key. Typed text isn't an
+// action; F11 replays the most recent repeatable action.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum Action {
+    Select(KSelection),
+    SelectPrevious,
+    SelectNext,
+    ToggleMaximize,
+    SwapWindows,
+    ExitToDirectory,
+    Scroll(isize),
+    MoveCursor(Direction),
+    Edit,
+    Run { step_mode: bool },
+    RunToOutputFile,
+    ToggleSysInfo,
+    StepAll,
+    ToggleFocusFollowsOutput,
+    CycleWindowColor,
+    ShowLastOutput,
+    OpenScratch,
+    DeleteFile,
+    RenameFile,
+    CopyFile,
+    ResetAll,
+    InsertTicks,
+    CutLine,
+    DuplicateLine,
+}
+
+impl Action {
+    // Deleting again asks again, so repeating a delete can't skip its
+    // confirmation.
+    fn is_repeatable(&self) -> bool {
+        !matches!(
+            self,
+            Action::Select(_) | Action::SelectPrevious | Action::SelectNext | Action::SwapWindows | Action::ResetAll
+        )
+    }
+}
+
+struct TypingBuffer<const MAX_LENGTH: usize> {
+    buffer: [u8; MAX_LENGTH],
+    cursor: usize,
+}
+
+impl<const MAX_LENGTH: usize> TypingBuffer<MAX_LENGTH> {
+    fn type_char(&mut self, c: char) {
+        let Some(byte) = input_byte(c, false) else { return };
+        if self.cursor < MAX_LENGTH {
+            self.buffer[self.cursor] = byte;
+            self.cursor += 1;
+        }
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.buffer[self.cursor - 1] = 0;
+            self.cursor -= 1;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.buffer = [0; MAX_LENGTH];
+        self.cursor = 0;
+    }
+
+    fn draw(&self, col: usize, row: usize, color: ColorCode) {
+        for i in 0..MAX_LENGTH.min(BUFFER_WIDTH.saturating_sub(col)) {
+            let char_to_plot = if i < self.cursor { self.buffer[i] as char } else { ' ' };
+            plot(char_to_plot, col + i, row, color);
+        }
+    }
+
+    fn get_bytes(&mut self) -> (usize, [u8; MAX_LENGTH]) {
+        (self.cursor, self.buffer.clone())
+    }
+}
+
+// Status messages waiting to be shown on the top row. The front message stays
+// up for at least `STATUS_MIN_TICKS` before the next one replaces it; the last
+// message stays until something newer arrives.
+struct StatusQueue {
+    messages: [&'static str; STATUS_QUEUE_LEN],
+    count: usize,
+    shown_since: usize,
+}
+
+impl StatusQueue {
+    fn new() -> Self {
+        Self { messages: [""; STATUS_QUEUE_LEN], count: 0, shown_since: 0 }
+    }
+
+    fn push(&mut self, message: &'static str, now: usize) {
+        self.advance(now);
+        if self.count == 1 && self.front_expired(now) {
+            self.pop(now);
+        }
+        if self.count == STATUS_QUEUE_LEN {
+            self.messages[STATUS_QUEUE_LEN - 1] = message;
+        } else {
+            if self.count == 0 {
+                self.shown_since = now;
+            }
+            self.messages[self.count] = message;
+            self.count += 1;
+        }
+    }
+
+    fn advance(&mut self, now: usize) {
+        while self.count > 1 && self.front_expired(now) {
+            self.pop(now);
+        }
+    }
+
+    fn front_expired(&self, now: usize) -> bool {
+        now.saturating_sub(self.shown_since) >= STATUS_MIN_TICKS
+    }
+
+    fn pop(&mut self, now: usize) {
+        self.messages.copy_within(1.., 0);
+        self.count -= 1;
+        self.shown_since = now;
+    }
+
+    fn current(&self) -> &'static str {
+        if self.count > 0 { self.messages[0] } else { "" }
+    }
+}
+
+// Where the keyboard was before a prompt took it, and the window the prompt
+// belongs to. A prompt's own state lives in its window's mode, so nothing
+// else needs keeping to back out of it.
+#[derive(Clone, Copy, Debug)]
+struct PromptReturn {
+    selected: KSelection,
+    window: KWindows,
+}
+
+// Open prompts, innermost last. A prompt opened while the stack is full pushes
+// out the outermost one.
+struct PromptStack {
+    entries: [Option<PromptReturn>; PROMPT_STACK_DEPTH],
+    count: usize,
+}
+
+impl PromptStack {
+    fn new() -> Self {
+        Self { entries: [None; PROMPT_STACK_DEPTH], count: 0 }
+    }
+
+    fn push(&mut self, entry: PromptReturn) {
+        if self.count == PROMPT_STACK_DEPTH {
+            self.entries.copy_within(1.., 0);
+            self.count -= 1;
+        }
+        self.entries[self.count] = Some(entry);
+        self.count += 1;
+    }
+
+    // Takes out the innermost prompt belonging to `window`. Prompts in
+    // different windows can be answered in any order.
+    fn pop(&mut self, window: KWindows) -> Option<PromptReturn> {
+        let index = self.entries[..self.count].iter().rposition(|entry| {
+            matches!(entry, Some(entry) if entry.window == window)
+        })?;
+        let entry = self.entries[index].take();
+        self.entries.copy_within(index + 1.., index);
+        self.count -= 1;
+        self.entries[self.count] = None;
+        entry
+    }
+
+    fn has(&self, window: KWindows) -> bool {
+        self.entries[..self.count].iter().any(|entry| matches!(entry, Some(entry) if entry.window == window))
+    }
+}
+
+// How window borders are drawn. `Ascii` is the fallback for fonts without
+// the CP437 box-drawing glyphs.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum BorderStyle { Ascii, BoxDrawing }
+
+// What happens to a dirty edit buffer when its window is about to be reset;
+// see `Kernel::guard_unsaved`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum UnsavedPolicy { Warn, AutoSave }
+
+// What Enter in the filebar does with the text typed there. Everything but
+// `Create` is a prompt belonging to the window it names.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum FilebarUse {
+    Create,
+    // Renames the file with this name.
+    Rename(KWindows, [u8; MAX_FILENAME_BYTES]),
+    // Copies the file with this name.
+    Copy(KWindows, [u8; MAX_FILENAME_BYTES]),
+    // Moves this editing window's cursor to the typed line number.
+    GoToLine(KWindows),
+    // Searches this editing window for the typed text.
+    Search(KWindows),
+    // Makes the typed `old/new` replacement in this editing window.
+    Replace(KWindows),
+}
+
+impl FilebarUse {
+    fn owner(&self) -> Option<KWindows> {
+        match *self {
+            FilebarUse::Create => None,
+            FilebarUse::Rename(window, _) | FilebarUse::Copy(window, _) => Some(window),
+            FilebarUse::GoToLine(window) | FilebarUse::Search(window) | FilebarUse::Replace(window) => Some(window),
+        }
+    }
+
+    // Search and replace type program text into `Kernel::text_buffer`
+    // instead of the filebar.
+    fn takes_text(&self) -> bool {
+        matches!(self, FilebarUse::Search(_) | FilebarUse::Replace(_))
+    }
+}
+
+// How a window is set up when the kernel starts; see `Kernel::with_startup`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum StartupMode {
+    Directory,
+    Edit(&'static str),
+    Run(&'static str),
+    Step(&'static str),
+}
+
+pub struct Kernel {
+    selected: KSelection,
+    previous_selected: KSelection,
+    // Index of the window `run_one_instruction` tries first.
+    next_to_run: usize,
+    filebar_buffer: TypingBuffer<MAX_FILENAME_BYTES>,
+    filebar_use: FilebarUse,
+    text_buffer: TypingBuffer<TEXT_PROMPT_BYTES>,
+    directory_cache: Option<DirectoryCache>,
+    window_modes: [KWindowMode; 4],
+    run_counts: [usize; NUM_WINDOWS],
+    // What each window was last running, kept after it goes back to the
+    // directory so its output can be shown again.
+    last_runs: [Option<LastRun>; NUM_WINDOWS],
+    last_action: Option<Action>,
+    ticks: usize,
+    status: StatusQueue,
+    prompts: PromptStack,
+    focus_follows_output: bool,
+    review_saves: bool,
+    // Instructions each window's most recent run-to-file took to finish.
+    headless_steps: [Option<usize>; NUM_WINDOWS],
+    unsaved_policy: UnsavedPolicy,
+    border_style: BorderStyle,
+    // The warning of the confirmation the last key asked for, and the one
+    // the current key is answering; see `Kernel::confirm`.
+    confirm_armed: Option<&'static str>,
+    confirmed: Option<&'static str>,
+    color_overrides: [Option<usize>; NUM_WINDOWS],
+    busy: [bool; NUM_WINDOWS],
+    busy_frame: usize,
+    // The window shown over the whole windows area. The other three keep
+    // running and keep their modes; they just aren't drawn.
+    maximized: Option<KWindows>,
+    // The window whose contents the next F1-F4 key swaps into that window.
+    swapping: Option<KWindows>,
+    fs: FileSystem<
+        MAX_OPEN, 
+        BLOCK_SIZE, 
+        NUM_BLOCKS, 
+        MAX_FILE_BLOCKS, 
+        MAX_FILE_BYTES, 
+        MAX_FILES_STORED, 
+        MAX_FILENAME_BYTES
+    >,
+}
+
+const HELLO: &str = r#"print("Hello, world!")"#;
+
+const NUMS: &str = r#"print(1)
+print(257)"#;
+
+const ADD_ONE: &str = r#"x := input("Enter a number")
+x := (x + 1)
+print(x)"#;
+
+const COUNTDOWN: &str = r#"count := input("count")
+while (count > 0) {
+    count := (count - 1)
+}
+print("done")
+print(count)"#;
+
+const AVERAGE: &str = r#"sum := 0
+count := 0
+averaging := true
+while averaging {
+    num := input("Enter a number:")
+    if (num == "quit") {
+        averaging := false
+    } else {
+        sum := (sum + num)
+        count := (count + 1)
+    }
+}
+print((sum / count))"#;
+
+const PI: &str = r#"sum := 0
 i := 0
 neg := false
 terms := input("Num terms:")
@@ -342,325 +1669,2920 @@ while (i < terms) {
     if neg {
         term := -term
     }
-    sum := (sum + term)
-    neg := not neg
-    i := (i + 1)
+    sum := (sum + term)
+    neg := not neg
+    i := (i + 1)
+}
+print((4 * sum))"#;
+
+// Seed the disk with some programs.
+fn initial_files(disk: &mut FileSystem<MAX_OPEN, BLOCK_SIZE, NUM_BLOCKS, MAX_FILE_BLOCKS, MAX_FILE_BYTES, MAX_FILES_STORED, MAX_FILENAME_BYTES>) {
+    for (filename, contents) in [
+        ("hello", HELLO),
+        ("nums", NUMS),
+        ("add_one", ADD_ONE),
+        ("countdown", COUNTDOWN),
+        ("average", AVERAGE),
+        ("pi", PI),
+    ] {
+        let fd = disk.open_create(filename).unwrap();
+        disk.write(fd, contents.as_bytes()).unwrap();
+        disk.close(fd);
+    }
+}
+
+impl Kernel {
+    pub fn new() -> Self {
+        let mut fs: FileSystem<
+            MAX_OPEN, 
+            BLOCK_SIZE, 
+            NUM_BLOCKS, 
+            MAX_FILE_BLOCKS, 
+            MAX_FILE_BYTES, 
+            MAX_FILES_STORED, 
+            MAX_FILENAME_BYTES
+        > = FileSystem::new(ramdisk::RamDisk::new());
+        initial_files(&mut fs);
+        let filebar_buffer = TypingBuffer {
+            buffer: [0u8; MAX_FILENAME_BYTES],
+            cursor: 0,
+        };
+        
+        Self {
+            selected: KSelection::Window(KWindows::F1),
+            previous_selected: KSelection::Filebar,
+            next_to_run: 0,
+            filebar_buffer,
+            filebar_use: FilebarUse::Create,
+            text_buffer: TypingBuffer { buffer: [0; TEXT_PROMPT_BYTES], cursor: 0 },
+            directory_cache: None,
+            window_modes: [KWindowMode::directory(0); NUM_WINDOWS],
+            run_counts: [0; NUM_WINDOWS],
+            last_runs: [None; NUM_WINDOWS],
+            last_action: None,
+            ticks: 0,
+            status: StatusQueue::new(),
+            prompts: PromptStack::new(),
+            focus_follows_output: false,
+            review_saves: false,
+            headless_steps: [None; NUM_WINDOWS],
+            unsaved_policy: UnsavedPolicy::Warn,
+            border_style: BorderStyle::Ascii,
+            confirm_armed: None,
+            confirmed: None,
+            color_overrides: [None; NUM_WINDOWS],
+            busy: [false; NUM_WINDOWS],
+            busy_frame: 0,
+            maximized: None,
+            swapping: None,
+            fs
+        }
+    }
+
+    // Starts with each window (F1 through F4, in order) set up as described by
+    // `startup`. Files that don't exist leave their window in directory mode.
+    pub fn with_startup(startup: [StartupMode; NUM_WINDOWS]) -> Self {
+        let mut kernel = Self::new();
+        for (window, mode) in [KWindows::F1, KWindows::F2, KWindows::F3, KWindows::F4].into_iter().zip(startup) {
+            let (filename, step_mode) = match mode {
+                StartupMode::Directory => continue,
+                StartupMode::Edit(filename) => (filename, None),
+                StartupMode::Run(filename) => (filename, Some(false)),
+                StartupMode::Step(filename) => (filename, Some(true)),
+            };
+            let Some(index) = kernel.find_file(filename) else { continue };
+            kernel.set_window_mode(window, KWindowMode::directory(index));
+            match step_mode {
+                None => kernel.switch_to_edit_mode(window),
+                Some(step_mode) => kernel.switch_to_run_mode(window, step_mode),
+            }
+        }
+        kernel
+    }
+
+    pub fn key(&mut self, key: DecodedKey) {
+        self.handle_key(key);
+        self.draw();
+    }
+
+    // Everything a key does but the redraw after it.
+    fn handle_key(&mut self, key: DecodedKey) {
+        self.confirmed = self.confirm_armed.take();
+        if let Some(window) = self.swapping.take() {
+            self.finish_swap(window, key);
+        } else {
+            match key {
+                DecodedKey::RawKey(code) => self.handle_raw(code),
+                DecodedKey::Unicode(c) => self.handle_unicode(c)
+            }
+        }
+    }
+
+    fn handle_raw(&mut self, key: KeyCode) {
+        match key {
+            KeyCode::F1 => self.perform(Action::Select(KSelection::Window(KWindows::F1))),
+            KeyCode::F2 => self.perform(Action::Select(KSelection::Window(KWindows::F2))),
+            KeyCode::F3 => self.perform(Action::Select(KSelection::Window(KWindows::F3))),
+            KeyCode::F4 => self.perform(Action::Select(KSelection::Window(KWindows::F4))),
+            KeyCode::F5 => self.perform(Action::Select(KSelection::Filebar)),
+            KeyCode::F6 => self.perform(Action::ExitToDirectory),
+            KeyCode::F7 => self.perform(Action::Scroll(-1)),
+            KeyCode::F8 => self.perform(Action::Scroll(1)),
+            KeyCode::PageUp => self.perform(Action::Scroll(-(self.page_height() as isize))),
+            KeyCode::PageDown => self.perform(Action::Scroll(self.page_height() as isize)),
+            KeyCode::F9 => self.perform(Action::StepAll),
+            KeyCode::F10 => self.perform(Action::ToggleFocusFollowsOutput),
+            KeyCode::F11 => self.repeat_last_action(),
+            KeyCode::F12 => self.perform(Action::ResetAll),
+            KeyCode::Insert => self.perform(Action::SelectPrevious),
+            KeyCode::ArrowUp    => self.perform(Action::MoveCursor(Direction::Up)),
+            KeyCode::ArrowDown  => self.perform(Action::MoveCursor(Direction::Down)),
+            KeyCode::ArrowLeft  => self.perform(Action::MoveCursor(Direction::Left)),
+            KeyCode::ArrowRight => self.perform(Action::MoveCursor(Direction::Right)),
+            KeyCode::Home       => self.perform(Action::MoveCursor(Direction::LineStart)),
+            KeyCode::End        => self.perform(Action::MoveCursor(Direction::LineEnd)),
+            _ => {}
+        }
+    }
+
+    fn perform(&mut self, action: Action) {
+        if action.is_repeatable() {
+            self.last_action = Some(action);
+        }
+        match action {
+            Action::Select(selection) => self.select(selection),
+            Action::SelectPrevious => self.select(self.previous_selected),
+            Action::SelectNext => self.select(match self.selected {
+                KSelection::Window(KWindows::F1) => KSelection::Window(KWindows::F2),
+                KSelection::Window(KWindows::F2) => KSelection::Window(KWindows::F3),
+                KSelection::Window(KWindows::F3) => KSelection::Window(KWindows::F4),
+                KSelection::Window(KWindows::F4) => KSelection::Filebar,
+                KSelection::Filebar => KSelection::Window(KWindows::F1),
+            }),
+            Action::ToggleMaximize => {
+                if let KSelection::Window(window) = self.selected {
+                    self.maximize(if self.maximized.is_some() { None } else { Some(window) });
+                }
+            },
+            Action::SwapWindows => {
+                if let KSelection::Window(window) = self.selected {
+                    self.swapping = Some(window);
+                    self.set_status("swap with which? F1-F4");
+                }
+            },
+            Action::ExitToDirectory => {
+                if let KSelection::Window(window) = self.selected {
+                    // A prompt left open would outlive the mode it belongs to.
+                    self.close_prompt(window, true);
+                    if self.review_saves && self.begin_save_review(window) { return }
+                    self.while_busy(window, |kernel| kernel.switch_to_directory_mode(window));
+                }
+            },
+            Action::Scroll(delta) => self.scroll_window(delta),
+            Action::MoveCursor(direction) => self.move_cursor(direction),
+            Action::Edit => {
+                if let KSelection::Window(window) = self.selected {
+                    self.while_busy(window, |kernel| kernel.switch_to_edit_mode(window));
+                }
+            },
+            Action::Run { step_mode } => {
+                if let KSelection::Window(window) = self.selected {
+                    self.while_busy(window, |kernel| kernel.switch_to_run_mode(window, step_mode));
+                }
+            },
+            Action::RunToOutputFile => {
+                if let KSelection::Window(window) = self.selected {
+                    self.while_busy(window, |kernel| kernel.run_to_output_file(window));
+                }
+            },
+            Action::ToggleSysInfo => {
+                if let KSelection::Window(window) = self.selected {
+                    self.toggle_sys_info(window);
+                }
+            },
+            Action::StepAll => self.step_all(),
+            Action::ToggleFocusFollowsOutput => {
+                self.focus_follows_output = !self.focus_follows_output;
+                self.set_status(if self.focus_follows_output {
+                    "focus follows output: on"
+                } else {
+                    "focus follows output: off"
+                });
+            },
+            Action::CycleWindowColor => {
+                if let KSelection::Window(window) = self.selected {
+                    self.cycle_window_color(window);
+                }
+            },
+            Action::OpenScratch => {
+                if let KSelection::Window(window) = self.selected {
+                    if let KWindowMode::Directory(_) = self.get_window_mode(window) {
+                        self.set_window_mode(window, KWindowMode::Scratch(ScratchState::new()));
+                    }
+                }
+            },
+            Action::ShowLastOutput => {
+                if let KSelection::Window(window) = self.selected {
+                    if let KWindowMode::Directory(_) = self.get_window_mode(window) {
+                        self.show_last_output(window);
+                    }
+                }
+            },
+            Action::DeleteFile => {
+                if let KSelection::Window(window) = self.selected {
+                    self.delete_selected_file(window);
+                }
+            },
+            Action::RenameFile => {
+                if let KSelection::Window(window) = self.selected {
+                    self.name_selected_file(window, FilebarUse::Rename);
+                }
+            },
+            Action::CopyFile => {
+                if let KSelection::Window(window) = self.selected {
+                    self.name_selected_file(window, FilebarUse::Copy);
+                }
+            },
+            Action::ResetAll => self.reset_all(),
+            Action::InsertTicks => {
+                let ticks = self.ticks;
+                self.edit_selected(|edit_state| {
+                    edit_state.insert_number(ticks);
+                    Ok(())
+                });
+            },
+            Action::CutLine => self.edit_selected(EditingState::cut_line),
+            Action::DuplicateLine => self.edit_selected(EditingState::duplicate_line),
+        }
+    }
+
+    // Applies `edit` to the selected window's buffer if it is editing, and
+    // shows the reason when the edit can't be made.
+    fn edit_selected(&mut self, edit: impl FnOnce(&mut EditingState) -> Result<(), &'static str>) {
+        let KSelection::Window(window) = self.selected else { return };
+        let KWindowMode::Editing(mut edit_state) = self.get_window_mode(window) else { return };
+        if let Err(message) = edit(&mut edit_state) {
+            self.set_status(message);
+        }
+        self.set_window_mode(window, KWindowMode::Editing(edit_state));
+    }
+
+    // Advances every runnable process by exactly one instruction, so several
+    // stepped programs move in lockstep.
+    fn step_all(&mut self) {
+        for window in [KWindows::F1, KWindows::F2, KWindows::F3, KWindows::F4] {
+            self.step_window(window);
+        }
+    }
+
+    // Steps the window's process in place, without copying its state out.
+    // Returns whether the window needs redrawing: it printed, or its process
+    // stopped to wait for input or finished.
+    fn step_window(&mut self, window: KWindows) -> bool {
+        let KWindowMode::Running(run_state) = &mut self.window_modes[window.index()] else { return false };
+        let status = run_state.status;
+        let printed = run_state.step(self.ticks);
+        let changed = printed || run_state.status != status;
+        // Output doesn't take the keyboard from the filebar, a prompt, or a
+        // confirmation still waiting for its key.
+        let answering = self.selected == KSelection::Filebar || self.prompts.count > 0 || self.confirm_armed.is_some();
+        if printed && self.focus_follows_output && !answering {
+            self.select(KSelection::Window(window));
+        }
+        changed
+    }
+
+    // Cycles the selection to the next window, in F1-F4 order, that is running
+    // the same source file as `window`.
+    fn select_next_running_same_file(&mut self, window: KWindows, filename: [u8; MAX_FILENAME_BYTES]) {
+        let windows = [KWindows::F1, KWindows::F2, KWindows::F3, KWindows::F4];
+        for offset in 1..NUM_WINDOWS {
+            let candidate = windows[(window.index() + offset) % NUM_WINDOWS];
+            if let KWindowMode::Running(other) = &self.window_modes[candidate.index()] {
+                if other.filename == filename {
+                    self.select(KSelection::Window(candidate));
+                    return;
+                }
+            }
+        }
+        self.set_status("no other window runs this file");
+    }
+
+    // Moves the keyboard to `selection`, remembering where it was so Insert
+    // can swap back.
+    fn select(&mut self, selection: KSelection) {
+        if selection != KSelection::Filebar {
+            // Leaving a filebar prompt by selecting something else drops it.
+            if let Some(window) = self.filebar_use.owner() {
+                self.prompts.pop(window);
+                self.filebar_buffer.clear();
+                self.text_buffer.clear();
+            }
+            self.filebar_use = FilebarUse::Create;
+        }
+        if selection != self.selected {
+            self.previous_selected = self.selected;
+            self.selected = selection;
+        }
+        // Selecting a hidden window shows it maximized in place of the other.
+        if let (Some(maximized), KSelection::Window(window)) = (self.maximized, selection) {
+            if window != maximized {
+                self.maximize(Some(window));
+            }
+        }
+    }
+
+    // Takes the key after Ctrl+P: F1-F4 swaps `window` with that window, and
+    // anything else cancels.
+    fn finish_swap(&mut self, window: KWindows, key: DecodedKey) {
+        let other = match key {
+            DecodedKey::RawKey(KeyCode::F1) => KWindows::F1,
+            DecodedKey::RawKey(KeyCode::F2) => KWindows::F2,
+            DecodedKey::RawKey(KeyCode::F3) => KWindows::F3,
+            DecodedKey::RawKey(KeyCode::F4) => KWindows::F4,
+            _ => {
+                self.set_status("swap cancelled");
+                return;
+            }
+        };
+        self.swap_windows(window, other);
+    }
+
+    // Trades what the two windows show, with all its state, and follows the
+    // contents of `window` to `other`. The last run and .out footer go along
+    // with them; run counts and colors stay with the window. A window with a
+    // prompt open is refused, since the prompt answers to its window.
+    fn swap_windows(&mut self, window: KWindows, other: KWindows) {
+        if window == other { return }
+        if self.prompts.has(window) || self.prompts.has(other) {
+            self.set_status("answer the open prompt first");
+            return;
+        }
+        let mode = self.get_window_mode(window);
+        self.set_window_mode(window, self.get_window_mode(other));
+        self.set_window_mode(other, mode);
+        self.last_runs.swap(window.index(), other.index());
+        self.headless_steps.swap(window.index(), other.index());
+        self.select(KSelection::Window(other));
+    }
+
+    // Shows `window` alone over the whole windows area, or with None goes back
+    // to the 2x2 grid. Every window's mode is resized to its new frame.
+    fn maximize(&mut self, window: Option<KWindows>) {
+        self.maximized = window;
+        for window in [KWindows::F1, KWindows::F2, KWindows::F3, KWindows::F4] {
+            self.set_window_mode(window, self.get_window_mode(window));
+        }
+        // Anything the new layout doesn't draw over would keep old borders.
+        for row in FIRST_BORDER_ROW..FIRST_BORDER_ROW + MAX_WINDOW_HEIGHT + 2 {
+            for col in 0..WINDOWS_WIDTH {
+                plot(' ', col, row, text_color());
+            }
+        }
+    }
+
+    // Where `window` is drawn: the whole windows area while it is maximized,
+    // otherwise its quarter.
+    fn frame(&self, window: KWindows) -> Frame {
+        if self.maximized == Some(window) {
+            Frame { col: 0, row: FIRST_BORDER_ROW, width: MAX_WINDOW_WIDTH, height: MAX_WINDOW_HEIGHT }
+        } else {
+            Frame { col: window.col(), row: window.row(), width: WINDOW_WIDTH, height: WINDOW_HEIGHT }
+        }
+    }
+
+    // PageUp and PageDown scroll the selected window by its own height.
+    fn page_height(&self) -> usize {
+        match self.selected {
+            KSelection::Window(window) => self.frame(window).height,
+            KSelection::Filebar => WINDOW_HEIGHT,
+        }
+    }
+
+    fn repeat_last_action(&mut self) {
+        if let Some(action) = self.last_action {
+            self.perform(action);
+        }
+    }
+
+    // Tab moves the selection on, F1-F4 then the filebar, everywhere but an
+    // editor, where it indents. Shift+Tab decodes to the same character, so
+    // there's no backward cycle; Insert swaps back to the last selection.
+    // Ctrl+L maximizes the selected window, or restores the grid, and Ctrl+P
+    // swaps it with another, from anywhere.
+    fn handle_unicode(&mut self, key: char) {
+        if key == MAXIMIZE_KEY {
+            self.perform(Action::ToggleMaximize);
+            return;
+        }
+        if key == SWAP_KEY {
+            self.perform(Action::SwapWindows);
+            return;
+        }
+        if key == '\t' && !matches!(
+            self.selected,
+            KSelection::Window(window) if matches!(self.get_window_mode(window), KWindowMode::Editing(_))
+        ) {
+            self.perform(Action::SelectNext);
+            return;
+        }
+        match self.selected {
+            KSelection::Filebar => {
+                match key {
+                    '\u{8}' if self.filebar_use.takes_text() => self.text_buffer.backspace(),
+                    '\u{8}' => self.filebar_buffer.backspace(),
+                    // Back to where the filebar was opened from, with nothing
+                    // typed kept.
+                    ESCAPE_KEY => match self.filebar_use.owner() {
+                        Some(window) => self.close_prompt(window, true),
+                        None => {
+                            self.filebar_buffer.clear();
+                            self.select(self.previous_selected);
+                        },
+                    },
+                    '\n' => match self.filebar_use {
+                        FilebarUse::Create => self.try_create_file(),
+                        FilebarUse::Rename(window, old_name) => self.try_rename_file(window, old_name),
+                        FilebarUse::Copy(window, source) => self.try_copy_file(window, source),
+                        FilebarUse::GoToLine(window) => self.try_go_to_line(window),
+                        FilebarUse::Search(window) => self.search_window(window),
+                        FilebarUse::Replace(window) => self.replace_in_window(window),
+                    },
+                    other if is_drawable(other) && self.filebar_use.takes_text() => self.text_buffer.type_char(other),
+                    other if is_drawable(other) => self.filebar_buffer.type_char(other),
+                    _ => {},
+                }
+            },
+            KSelection::Window(window) => {
+                match self.get_window_mode(window) {
+                    KWindowMode::Directory(_) => {
+                        match key {
+                            'e' => self.perform(Action::Edit),
+                            'r' => self.perform(Action::Run { step_mode: false }),
+                            'R' => self.perform(Action::Run { step_mode: true }),
+                            'x' => self.perform(Action::RunToOutputFile),
+                            'i' => self.perform(Action::ToggleSysInfo),
+                            'k' => self.perform(Action::CycleWindowColor),
+                            'o' => self.perform(Action::ShowLastOutput),
+                            's' => self.perform(Action::OpenScratch),
+                            'd' => self.perform(Action::DeleteFile),
+                            'n' => self.perform(Action::RenameFile),
+                            'c' => self.perform(Action::CopyFile),
+                            _ => {},
+                        }
+                    },
+                    KWindowMode::Editing(mut edit_state) if edit_state.save_review.is_some() => {
+                        match key {
+                            'y' => {
+                                self.close_prompt(window, false);
+                                edit_state.save_review = None;
+                                self.set_window_mode(window, KWindowMode::Editing(edit_state));
+                                self.while_busy(window, |kernel| kernel.switch_to_directory_mode(window));
+                            },
+                            'n' | ESCAPE_KEY => self.close_prompt(window, true),
+                            _ => {},
+                        }
+                    },
+                    KWindowMode::Editing(mut edit_state) if edit_state.reindent_prompt => {
+                        match key {
+                            '1'..='8' => {
+                                self.close_prompt(window, false);
+                                edit_state.reindent_prompt = false;
+                                if let Err(message) = edit_state.reindent(key as usize - '0' as usize) {
+                                    self.set_status(message);
+                                }
+                                self.set_window_mode(window, KWindowMode::Editing(edit_state));
+                            },
+                            ESCAPE_KEY => self.close_prompt(window, true),
+                            _ => {},
+                        }
+                    },
+                    KWindowMode::Editing(mut edit_state) => {
+                        match key {
+                            '\n' | ' '..='~' if edit_state.len == PRACTICAL_FILE_BUFFER_SIZE => {
+                                self.set_status("edit buffer full");
+                            },
+                            '\t' if edit_state.len + TAB_SPACES > PRACTICAL_FILE_BUFFER_SIZE => {
+                                self.set_status("edit buffer full");
+                            },
+                            '\t' => {
+                                for _ in 0..TAB_SPACES {
+                                    edit_state.type_char(' ');
+                                }
+                            },
+                            '\n' => edit_state.type_char('\n'),
+                            key if is_drawable(key) => edit_state.type_char(key),
+                            '\u{8}' => edit_state.backspace(),
+                            DELETE_KEY => edit_state.delete(),
+                            SHOW_BYTE_KEY => edit_state.show_cursor_byte = !edit_state.show_cursor_byte,
+                            // These go through `perform` so F11 can repeat
+                            // them. Nothing has changed this copy of the
+                            // buffer yet, so the window's own is current.
+                            INSERT_TICKS_KEY | CUT_LINE_KEY | DUPLICATE_LINE_KEY => {
+                                self.perform(match key {
+                                    INSERT_TICKS_KEY => Action::InsertTicks,
+                                    CUT_LINE_KEY => Action::CutLine,
+                                    _ => Action::DuplicateLine,
+                                });
+                                return;
+                            },
+                            TRUNCATE_KEY if edit_state.cursor == edit_state.len => {
+                                self.set_status("nothing after the cursor");
+                            },
+                            TRUNCATE_KEY => {
+                                if self.confirm("cut the rest of the file? ^K again") {
+                                    edit_state.truncate_at_cursor();
+                                }
+                            },
+                            REINDENT_KEY => {
+                                if self.open_prompt(window) {
+                                    edit_state.reindent_prompt = true;
+                                }
+                            },
+                            FOLD_KEY => {
+                                if let Err(message) = edit_state.toggle_fold() {
+                                    self.set_status(message);
+                                }
+                            },
+                            UNDO_KEY => {
+                                if let Err(message) = edit_state.undo() {
+                                    self.set_status(message);
+                                }
+                            },
+                            REDO_KEY => {
+                                if let Err(message) = edit_state.redo() {
+                                    self.set_status(message);
+                                }
+                            },
+                            AUTOSAVE_KEY => {
+                                self.unsaved_policy = match self.unsaved_policy {
+                                    UnsavedPolicy::Warn => UnsavedPolicy::AutoSave,
+                                    UnsavedPolicy::AutoSave => UnsavedPolicy::Warn,
+                                };
+                                self.set_status(match self.unsaved_policy {
+                                    UnsavedPolicy::Warn => "unsaved edits: warn",
+                                    UnsavedPolicy::AutoSave => "unsaved edits: autosave",
+                                });
+                            },
+                            GO_TO_LINE_KEY => {
+                                self.filebar_buffer.clear();
+                                self.open_filebar_prompt(FilebarUse::GoToLine(window));
+                            },
+                            SEARCH_KEY => {
+                                self.text_buffer.clear();
+                                self.open_filebar_prompt(FilebarUse::Search(window));
+                            },
+                            REPLACE_KEY => {
+                                self.text_buffer.clear();
+                                self.open_filebar_prompt(FilebarUse::Replace(window));
+                            },
+                            FIND_NEXT_KEY if edit_state.search_len == 0 => self.set_status("^W to search first"),
+                            FIND_NEXT_KEY => {
+                                if !edit_state.find(edit_state.cursor + 1) {
+                                    self.set_status("not found");
+                                }
+                            },
+                            SAVE_KEY => {
+                                // Saving works from the stored window mode, so
+                                // the edits so far go there first.
+                                self.set_window_mode(window, KWindowMode::Editing(edit_state));
+                                if self.save_editing_file(window) {
+                                    edit_state.dirty = false;
+                                    edit_state.detached = false;
+                                    self.set_status("saved");
+                                }
+                            },
+                            REVIEW_SAVES_KEY => {
+                                self.review_saves = !self.review_saves;
+                                self.set_status(if self.review_saves {
+                                    "review before saving: on"
+                                } else {
+                                    "review before saving: off"
+                                });
+                            },
+                            _ => {},
+                        }
+                        self.set_window_mode(window, KWindowMode::Editing(edit_state));
+                    },
+                    KWindowMode::Running(mut run_state) => {
+                        match key {
+                            _ if run_state.more_prompt => run_state.acknowledge_output(),
+                            '\n' if run_state.status == ProcessStatus::AwaitingInput => run_state.submit_input(),
+                            '\u{8}' if run_state.status == ProcessStatus::AwaitingInput => run_state.backspace(),
+                            key if run_state.status == ProcessStatus::AwaitingInput => run_state.type_char(key),
+                            't' => run_state.show_ticks = !run_state.show_ticks,
+                            'w' => {
+                                run_state.wrap_output = !run_state.wrap_output;
+                                run_state.hscroll = 0;
+                            },
+                            'j' => self.select_next_running_same_file(window, run_state.filename),
+                            // Enter only reruns a finished program, so a stray
+                            // Enter can't throw away a run in progress.
+                            'r' | '\n' if key == 'r' || run_state.status == ProcessStatus::Finished => {
+                                run_state.rerun();
+                                self.run_counts[window.index()] += 1;
+                            },
+                            'm' => {
+                                run_state.pager = !run_state.pager;
+                                run_state.acknowledge_output();
+                            },
+                            'o' => {
+                                run_state.pause_on_output = !run_state.pause_on_output;
+                                run_state.paused = run_state.pause_on_output || run_state.step_mode;
+                            },
+                            // The scheduler runs it up to the next print, where
+                            // `step` pauses it again.
+                            ' ' if run_state.pause_on_output => run_state.paused = false,
+                            // `p` switches a run in progress into step mode,
+                            // where `n` gives it one instruction at a time,
+                            // and back to running on its own.
+                            'p' => {
+                                run_state.step_mode = !run_state.step_mode;
+                                run_state.paused = run_state.step_mode || run_state.pause_on_output;
+                            },
+                            'n' if run_state.paused => {
+                                run_state.step(self.ticks);
+                            },
+                            _ => {},
+                        }
+                        self.set_window_mode(window, KWindowMode::Running(run_state));
+                    },
+                    KWindowMode::SysInfo => {
+                        match key {
+                            'i' => self.perform(Action::ToggleSysInfo),
+                            'b' => {
+                                self.border_style = match self.border_style {
+                                    BorderStyle::Ascii => BorderStyle::BoxDrawing,
+                                    BorderStyle::BoxDrawing => BorderStyle::Ascii,
+                                };
+                            },
+                            _ => {},
+                        }
+                    },
+                    KWindowMode::Scratch(mut scratch) => {
+                        match key {
+                            '\n' => scratch.evaluate(),
+                            '\u{8}' => scratch.backspace(),
+                            key if is_drawable(key) => scratch.type_char(key),
+                            _ => {},
+                        }
+                        self.set_window_mode(window, KWindowMode::Scratch(scratch));
+                    },
+                }
+            },
+        }
+    }
+
+    pub fn draw(&mut self) {
+        self.draw_frame();
+        present();
+    }
+
+    fn draw_frame(&mut self) {
+        plot_str(FILENAME_PROMPT, 0, 0, text_color());
+        self.filebar_buffer.draw(FILENAME_PROMPT.len(), 0, text_color());
+        self.draw_status();
+        self.draw_input_target();
+        self.draw_hint();
+        if let Some(window) = self.maximized {
+            self.draw_window(window);
+        } else {
+            for window in [KWindows::F1, KWindows::F2, KWindows::F3, KWindows::F4] {
+                self.draw_window(window);
+            }
+            if let KSelection::Window(window) = self.selected {
+                self.draw_window(window)
+            }
+        }
+        for window in [KWindows::F1, KWindows::F2, KWindows::F3, KWindows::F4] {
+            if self.maximized.is_some_and(|maximized| maximized != window) { continue }
+            let frame = self.frame(window);
+            plot_str(
+                window.name(),
+                frame.col + frame.label_col_offset(),
+                frame.row,
+                self.window_color(window),
+            );
+        }
+    }
+
+    pub fn set_ticks(&mut self, ticks: usize) {
+        self.ticks = ticks;
+    }
+
+    pub fn draw_proc_status(&mut self) {
+        self.draw_status();
+        for window in [KWindows::F1, KWindows::F2, KWindows::F3, KWindows::F4] {
+            let base_row = FIRST_BORDER_ROW + window.index() * TASK_ROWS_PER_WINDOW;
+            for row_offset in 0..TASK_ROWS_PER_WINDOW {
+                for col_offset in 0..TASK_MANAGER_WIDTH {
+                    plot(' ', TASK_MANAGER_COL + col_offset, base_row + row_offset, text_color());
+                }
+            }
+            plot_str(window.name(), TASK_MANAGER_COL, base_row + TASK_NAME_ROW, text_color());
+            self.draw_busy(window);
+            plot_str(RUNS_LABEL, TASK_MANAGER_COL, base_row + TASK_RUNS_ROW, text_color());
+            plot_num(
+                self.run_counts[window.index()] as isize,
+                TASK_MANAGER_COL + RUNS_LABEL.len(),
+                base_row + TASK_RUNS_ROW,
+                text_color(),
+            );
+            let (mode, glyph) = match &self.window_modes[window.index()] {
+                KWindowMode::Directory(_) => ("dir", None),
+                KWindowMode::Editing(_) => ("edit", None),
+                KWindowMode::Running(run_state) => ("run", Some(run_state_glyph(run_state))),
+                KWindowMode::SysInfo => ("info", None),
+                KWindowMode::Scratch(_) => ("repl", None),
+            };
+            plot_str(mode, TASK_MANAGER_COL, base_row + TASK_MODE_ROW, text_color());
+            if let Some(glyph) = glyph {
+                plot(glyph, TASK_MANAGER_COL + mode.len() + 1, base_row + TASK_MODE_ROW, text_color());
+            }
+            if let KWindowMode::Running(run_state) = &self.window_modes[window.index()] {
+                let steps = run_state.instructions_run;
+                let steps_col = TASK_MANAGER_COL + TASK_MANAGER_WIDTH.saturating_sub(num_digits(steps));
+                plot_num(steps as isize, steps_col, base_row + TASK_STEPS_ROW, text_color());
+            }
+        }
+        present();
+    }
+
+    // Runs a file operation for `window` with its busy spinner showing. The
+    // operation is synchronous, so the spinner is drawn right away rather than
+    // waiting for the next tick.
+    fn while_busy<T>(&mut self, window: KWindows, operation: impl FnOnce(&mut Self) -> T) -> T {
+        self.busy[window.index()] = true;
+        self.busy_frame = (self.busy_frame + 1) % BUSY_FRAMES.len();
+        self.draw_busy(window);
+        present();
+        let result = operation(self);
+        self.busy[window.index()] = false;
+        self.draw_busy(window);
+        present();
+        result
+    }
+
+    fn draw_busy(&self, window: KWindows) {
+        let glyph = if self.busy[window.index()] { BUSY_FRAMES[self.busy_frame] } else { ' ' };
+        let row = FIRST_BORDER_ROW + window.index() * TASK_ROWS_PER_WINDOW + TASK_NAME_ROW;
+        plot(glyph, TASK_MANAGER_COL + TASK_MANAGER_WIDTH - 1, row, text_color());
+    }
+
+    // Gives one instruction to the next process, in round-robin order from
+    // `next_to_run`, that can take one. Paused and finished processes, those
+    // waiting for input, and those held at a "-- more --" prompt are skipped.
+    pub fn run_one_instruction(&mut self) {
+        let windows = [KWindows::F1, KWindows::F2, KWindows::F3, KWindows::F4];
+        for offset in 0..NUM_WINDOWS {
+            let window = windows[(self.next_to_run + offset) % NUM_WINDOWS];
+            let KWindowMode::Running(run_state) = &self.window_modes[window.index()] else { continue };
+            if run_state.paused || run_state.more_prompt || run_state.status != ProcessStatus::Runnable { continue }
+            self.next_to_run = (window.index() + 1) % NUM_WINDOWS;
+            if self.step_window(window) {
+                self.draw();
+            }
+            return;
+        }
+    }
+
+    // The keys that do something for whatever currently has the keyboard.
+    fn hint(&self) -> &'static str {
+        match self.selected {
+            KSelection::Filebar => match self.filebar_use {
+                FilebarUse::Create => FILEBAR_HINT,
+                FilebarUse::Rename(..) => RENAME_HINT,
+                FilebarUse::Copy(..) => COPY_HINT,
+                FilebarUse::GoToLine(_) => GO_TO_LINE_HINT,
+                FilebarUse::Search(_) => SEARCH_HINT,
+                FilebarUse::Replace(_) => REPLACE_HINT,
+            },
+            KSelection::Window(window) => match self.window_modes[window.index()] {
+                KWindowMode::Directory(_) => DIRECTORY_HINT,
+                KWindowMode::Editing(_) => EDITING_HINT,
+                KWindowMode::Running(_) => RUNNING_HINT,
+                KWindowMode::SysInfo => SYS_INFO_HINT,
+                KWindowMode::Scratch(_) => SCRATCH_HINT,
+            },
+        }
+    }
+
+    fn draw_hint(&self) {
+        let hint = self.hint();
+        plot_str_clamped(hint, 0, HINT_ROW, BUFFER_WIDTH, text_color());
+        for col in hint.len().min(BUFFER_WIDTH)..BUFFER_WIDTH {
+            plot(' ', col, HINT_ROW, text_color());
+        }
+    }
+
+    // Names whatever the next keystroke goes to, since the filebar and a
+    // window waiting for input can both be taking text.
+    fn draw_input_target(&self) {
+        for col in INPUT_TARGET_COL..BUFFER_WIDTH {
+            plot(' ', col, 0, text_color());
+        }
+        plot_str(INPUT_TARGET_PREFIX, INPUT_TARGET_COL, 0, text_color());
+        let col = INPUT_TARGET_COL + INPUT_TARGET_PREFIX.len();
+        match self.selected {
+            KSelection::Filebar => {
+                plot_str("filebar", col, 0, highlight_color());
+            },
+            KSelection::Window(window) => {
+                let mode = match self.window_modes[window.index()] {
+                    KWindowMode::Directory(_) => "(dir)",
+                    KWindowMode::Editing(_) => "(edit)",
+                    KWindowMode::Running(_) => "(run)",
+                    KWindowMode::SysInfo => "(info)",
+                    KWindowMode::Scratch(_) => "(repl)",
+                };
+                plot_str(window.name(), col, 0, highlight_color());
+                plot_str(mode, col + window.name().len() + 1, 0, text_color());
+            },
+        }
+    }
+
+    fn window_color(&self, window: KWindows) -> ColorCode {
+        match self.color_overrides[window.index()] {
+            Some(palette_index) => ColorCode::new(WINDOW_PALETTE[palette_index], Color::Black),
+            None => text_color(),
+        }
+    }
+
+    // Steps the window's override through `WINDOW_PALETTE` and back to the
+    // default text color.
+    fn cycle_window_color(&mut self, window: KWindows) {
+        let color_override = &mut self.color_overrides[window.index()];
+        *color_override = match *color_override {
+            None => Some(0),
+            Some(palette_index) if palette_index + 1 < WINDOW_PALETTE.len() => Some(palette_index + 1),
+            Some(_) => None,
+        };
+    }
+
+    fn set_status(&mut self, message: &'static str) {
+        self.status.push(message, self.ticks);
+        self.draw_status();
+    }
+
+    fn draw_status(&mut self) {
+        self.status.advance(self.ticks);
+        let message = self.status.current();
+        plot_str_clamped(message, STATUS_COL, 0, STATUS_WIDTH, text_color());
+        for col in STATUS_COL + message.len().min(STATUS_WIDTH)..INPUT_TARGET_COL {
+            plot(' ', col, 0, text_color());
+        }
+    }
+
+    fn draw_window(&mut self, window: KWindows) {
+        self.clear_window(window);
+        self.draw_window_border(window);
+        let frame = self.frame(window);
+        let (col, row) = (frame.col, frame.row);
+        let label_col_offset = frame.label_col_offset();
+        let color = self.window_color(window);
+        match self.get_window_mode(window) {
+            KWindowMode::Directory(dir_state) => {
+                let (file_count, filenames) = self.list_directory();
+                if file_count == 0 {
+                    let message_row = row + frame.height / 2;
+                    plot_str_centered(NO_FILES_MESSAGE, col + 1, message_row, frame.width, color);
+                    plot_str_centered(NO_FILES_HINT, col + 1, message_row + 1, frame.width, color);
+                }
+                if let Some(steps) = self.headless_steps[window.index()] {
+                    let footer_row = row + frame.height + 1;
+                    let footer_col = col + FILENAME_LABEL_COL_OFFSET;
+                    plot_str(OUTPUT_FILE_STEPS_LABEL, footer_col, footer_row, color);
+                    plot_num(steps as isize, footer_col + OUTPUT_FILE_STEPS_LABEL.len(), footer_row, color);
+                    plot_str(STEPS_LABEL, footer_col + OUTPUT_FILE_STEPS_LABEL.len() + num_digits(steps), footer_row, color);
+                }
+                let first_file = dir_state.scroll * DIRECTORY_COLUMNS;
+                let last_file = file_count.min(first_file + frame.height * DIRECTORY_COLUMNS);
+                let cell_width = frame.width / DIRECTORY_COLUMNS;
+                for file in first_file..last_file {
+                    let cell_col = col + 1 + file % DIRECTORY_COLUMNS * cell_width;
+                    let cell_row = row + 1 + file / DIRECTORY_COLUMNS - dir_state.scroll;
+                    let cell_color = if file == dir_state.cursor { highlight_color() } else { color };
+                    let filename = filename_str(&filenames[file]);
+                    let size = self.directory().sizes[file];
+                    let size_col = cell_width - 1 - num_digits(size);
+                    for i in 0..cell_width - 1 {
+                        plot(' ', cell_col + i, cell_row, cell_color);
+                    }
+                    // A long name is cut short rather than run into its size.
+                    plot_str_clamped(filename, cell_col, cell_row, size_col - 1, cell_color);
+                    plot_num(size as isize, cell_col + size_col, cell_row, cell_color);
+                }
+            },
+            KWindowMode::Editing(edit_state) => {
+                plot_str_clamped(
+                    EDIT_MODE_HEADER,
+                    col + FILENAME_LABEL_COL_OFFSET,
+                    row,
+                    label_col_offset - FILENAME_LABEL_COL_OFFSET,
+                    color,
+                );
+                for i in 0..edit_state.filename.len() {
+                    if edit_state.filename[i] == 0 { continue }
+                    plot(
+                        edit_state.filename[i] as char,
+                        col + i + EDIT_MODE_HEADER.len() + FILENAME_LABEL_COL_OFFSET,
+                        row,
+                        color
+                    );
+                }
+                if edit_state.dirty {
+                    let name_len = filename_str(&edit_state.filename).len();
+                    plot(DIRTY_MARKER, col + name_len + EDIT_MODE_HEADER.len() + FILENAME_LABEL_COL_OFFSET, row, color);
+                }
+                // The cursor's displayed line and column, counting from 1, or
+                // the byte under it with ^B, end a space short of the window
+                // label.
+                if edit_state.show_cursor_byte {
+                    draw_cursor_byte(&edit_state, col + label_col_offset - 1, row, color);
+                } else {
+                    let (cursor_line, cursor_col) = edit_state.cursor_position();
+                    let position_width = num_digits(cursor_line + 1) + 1 + num_digits(cursor_col + 1);
+                    let position_col = col + label_col_offset - 1 - position_width;
+                    plot_num((cursor_line + 1) as isize, position_col, row, color);
+                    plot(':', position_col + num_digits(cursor_line + 1), row, color);
+                    plot_num((cursor_col + 1) as isize, position_col + num_digits(cursor_line + 1) + 1, row, color);
+                }
+                if let Some(review) = &edit_state.save_review {
+                    draw_save_review(review, frame, color);
+                } else {
+                    draw_edit_text(&edit_state, col, row, color);
+                }
+                if self.filebar_use.takes_text() && self.filebar_use.owner() == Some(window) {
+                    self.text_buffer.draw(col + FILENAME_LABEL_COL_OFFSET, row + frame.height + 1, highlight_color());
+                }
+            },
+            KWindowMode::SysInfo => {
+                plot_str_clamped(
+                    SYS_INFO_HEADER,
+                    col + FILENAME_LABEL_COL_OFFSET,
+                    row,
+                    label_col_offset - FILENAME_LABEL_COL_OFFSET,
+                    color,
+                );
+                self.draw_sys_info(frame, color);
+            },
+            KWindowMode::Scratch(scratch) => {
+                plot_str_clamped(
+                    SCRATCH_HEADER,
+                    col + FILENAME_LABEL_COL_OFFSET,
+                    row,
+                    label_col_offset - FILENAME_LABEL_COL_OFFSET,
+                    color,
+                );
+                draw_scratch(&scratch, frame, color);
+            },
+            KWindowMode::Running(run_state) => draw_running_window(&run_state, frame, color),
+        }
+    }
+
+    fn draw_sys_info(&mut self, frame: Frame, color: ColorCode) {
+        let Frame { col, row, height, .. } = frame;
+        let directory = self.directory();
+        let blocks_used = self.blocks_used();
+        let running = self.window_modes.iter()
+            .filter(|mode| matches!(mode, KWindowMode::Running(_)))
+            .count();
+        let rows = [
+            ("NUM_BLOCKS", NUM_BLOCKS),
+            ("BLOCK_SIZE", BLOCK_SIZE),
+            ("MAX_FILES_STORED", MAX_FILES_STORED),
+            ("MAX_FILE_BYTES", MAX_FILE_BYTES),
+            ("HEAP_SIZE", HEAP_SIZE),
+            ("MAX_TOKENS", MAX_TOKENS),
+            ("files", directory.file_count),
+            ("data blocks used", blocks_used),
+            ("ticks", self.ticks),
+            ("processes running", running),
+        ];
+        for (i, (label, value)) in rows.iter().take(height).enumerate() {
+            plot_str_clamped(label, col + 1, row + 1 + i, SYS_INFO_VALUE_COL_OFFSET - 1, color);
+            plot_num(*value as isize, col + SYS_INFO_VALUE_COL_OFFSET, row + 1 + i, color);
+        }
+    }
+
+    fn draw_window_border(&mut self, window: KWindows) {
+        let Frame { col, row, width, height } = self.frame(window);
+        let color = self.window_color(window);
+        let selected = self.selected == KSelection::Window(window);
+        let (horizontal, vertical) = match (self.border_style, selected) {
+            (BorderStyle::Ascii, true) => ('*', '*'),
+            (BorderStyle::Ascii, false) => ('.', '.'),
+            (BorderStyle::BoxDrawing, true) => (cp437(0xCD), cp437(0xBA)),
+            (BorderStyle::BoxDrawing, false) => (cp437(0xC4), cp437(0xB3)),
+        };
+        for col_offset in 0..width+2 {
+            plot(horizontal, col + col_offset, row, color);
+            plot(horizontal, col + col_offset, row + height+1, color);
+        }
+        for row_offset in 0..height+2 {
+            plot(vertical, col, row + row_offset, color);
+            plot(vertical, col + width+1, row + row_offset, color);
+        }
+        if self.border_style == BorderStyle::BoxDrawing {
+            for (left, top) in [(true, true), (false, true), (true, false), (false, false)] {
+                let corner_col = if left { col } else { col + width + 1 };
+                let corner_row = if top { row } else { row + height + 1 };
+                plot(box_corner(window, left, top, selected, self.maximized.is_some()), corner_col, corner_row, color);
+            }
+        }
+    }
+
+    fn clear_window(&mut self, window: KWindows) {
+        let Frame { col, row, width, height } = self.frame(window);
+        for col_offset in 1..width+1 {
+            for row_offset in 1..height+1 {
+                plot(' ', col + col_offset, row + row_offset, text_color());
+            }
+        }
+    }
+
+    // Once the file is created the keyboard goes back to where the filebar
+    // was opened from. The typed name stays in the filebar when the file
+    // can't be created, so it can be corrected. An existing name is refused
+    // rather than emptied.
+    fn try_create_file(&mut self) {
+        let (name_len, name_bytes) = self.filebar_buffer.get_bytes();
+        if let Ok(str) = str::from_utf8(&name_bytes[0..name_len]) {
+            if let Err(message) = check_filename(str) {
+                self.set_status(message);
+                return;
+            }
+            if self.find_file(str).is_some() {
+                self.set_status("file already exists");
+                return;
+            }
+            match self.fs.open_create(str) {
+                Ok(new_file) => {
+                    self.files_changed();
+                    if self.fs.close(new_file).is_ok() {
+                        self.filebar_buffer.clear();
+                        self.select(self.previous_selected);
+                    } else {
+                        self.set_status("couldn't close new file");
+                    }
+                },
+                Err(error) => self.report_open_error(error),
+            }
+        }
+    }
+
+    // Moves to the filebar with the name of the file under a directory
+    // window's cursor, ready to be edited into the name to rename or copy it
+    // to.
+    fn name_selected_file(&mut self, window: KWindows, filebar_use: fn(KWindows, [u8; MAX_FILENAME_BYTES]) -> FilebarUse) {
+        let KWindowMode::Directory(dir_state) = self.get_window_mode(window) else { return };
+        let (file_count, directory) = self.list_directory();
+        if dir_state.cursor >= file_count { return }
+        self.filebar_buffer.clear();
+        for &byte in filename_str(&directory[dir_state.cursor]).as_bytes() {
+            self.filebar_buffer.type_char(byte as char);
+        }
+        self.open_filebar_prompt(filebar_use(window, directory[dir_state.cursor]));
+    }
+
+    // Moves the keyboard to the filebar for a prompt belonging to the
+    // window `filebar_use` names.
+    fn open_filebar_prompt(&mut self, filebar_use: FilebarUse) {
+        let Some(window) = filebar_use.owner() else { return };
+        if !self.open_prompt(window) { return }
+        self.select(KSelection::Filebar);
+        self.filebar_use = filebar_use;
+    }
+
+    // Writes a copy of `source` under the name in the filebar. A name that is
+    // already taken is refused, and the filebar keeps it so it can be
+    // corrected.
+    fn try_copy_file(&mut self, window: KWindows, source: [u8; MAX_FILENAME_BYTES]) {
+        let (name_len, name_bytes) = self.filebar_buffer.get_bytes();
+        let Ok(destination) = str::from_utf8(&name_bytes[0..name_len]) else { return };
+        if let Err(message) = check_filename(destination) {
+            self.set_status(message);
+            return;
+        }
+        if self.find_file(destination).is_some() {
+            self.set_status("file already exists");
+            return;
+        }
+        if self.copy_file(filename_str(&source), destination) {
+            self.set_status("file copied");
+            self.close_prompt(window, false);
+        }
+    }
+
+    // Reads `source` whole and writes it to a new file `destination`,
+    // reporting whatever stops it. A copy cut short by a full disk is
+    // removed.
+    fn copy_file(&mut self, source: &str, destination: &str) -> bool {
+        let mut buffer = [0u8; PRACTICAL_FILE_BUFFER_SIZE];
+        let file = match self.fs.open_read(source) {
+            Ok(file) => file,
+            Err(error) => {
+                self.report_open_error(error);
+                return false;
+            }
+        };
+        let read = self.fs.read(file, &mut buffer);
+        self.fs.close(file).ok();
+        let Ok(len) = read else {
+            self.set_status("couldn't read file");
+            return false;
+        };
+        let file = match self.fs.open_create(destination) {
+            Ok(file) => {
+                self.files_changed();
+                file
+            },
+            Err(error) => {
+                self.report_open_error(error);
+                return false;
+            }
+        };
+        let written = self.fs.write(file, &buffer[..len]);
+        let closed = self.fs.close(file);
+        if let Err(error) = written.and(closed) {
+            self.fs.delete(destination).ok();
+            self.report_write_error(error);
+            return false;
+        }
+        true
+    }
+
+    // Copies the file being renamed to the name in the filebar and deletes
+    // the original. A name that is already taken is refused, and the filebar
+    // keeps it so it can be corrected.
+    fn try_rename_file(&mut self, window: KWindows, old_name: [u8; MAX_FILENAME_BYTES]) {
+        let (name_len, name_bytes) = self.filebar_buffer.get_bytes();
+        let Ok(new_name) = str::from_utf8(&name_bytes[0..name_len]) else { return };
+        let old_name = filename_str(&old_name);
+        if let Err(message) = check_filename(new_name) {
+            self.set_status(message);
+            return;
+        }
+        if new_name != old_name && self.find_file(new_name).is_some() {
+            self.set_status("file already exists");
+            return;
+        }
+        if new_name != old_name {
+            if !self.copy_file(old_name, new_name) { return }
+            self.files_changed();
+            if let Err(error) = self.fs.delete(old_name) {
+                self.report_open_error(error);
+                return;
+            }
+            // Buffers open on the file follow it to its new name.
+            for mode in self.window_modes.iter_mut() {
+                if let KWindowMode::Editing(edit_state) = mode {
+                    if filename_str(&edit_state.filename) == old_name {
+                        edit_state.filename = name_bytes;
+                    }
+                }
+            }
+            self.set_status("file renamed");
+        }
+        self.close_prompt(window, false);
+    }
+
+    // Searches an editing window for the text typed for it, from its cursor
+    // on, and hands the keyboard back to it.
+    fn search_window(&mut self, window: KWindows) {
+        let KWindowMode::Editing(mut edit_state) = self.get_window_mode(window) else { return };
+        let (len, bytes) = self.text_buffer.get_bytes();
+        if len == 0 {
+            self.set_status("type the text to find");
+            return;
+        }
+        edit_state.search = bytes;
+        edit_state.search_len = len;
+        if !edit_state.find(edit_state.cursor) {
+            self.set_status("not found");
+        }
+        self.set_window_mode(window, KWindowMode::Editing(edit_state));
+        self.close_prompt(window, false);
+    }
+
+    // Makes the `old/new` replacement typed for an editing window, once from
+    // its cursor on or everywhere with a trailing `/g`, and hands the
+    // keyboard back to it.
+    fn replace_in_window(&mut self, window: KWindows) {
+        let KWindowMode::Editing(mut edit_state) = self.get_window_mode(window) else { return };
+        let (len, bytes) = self.text_buffer.get_bytes();
+        let Ok(typed) = str::from_utf8(&bytes[..len]) else { return };
+        let mut parts = typed.split('/');
+        let (Some(old), Some(new)) = (parts.next(), parts.next()) else {
+            self.set_status("type old/new");
+            return;
+        };
+        let all = match parts.next() {
+            None => false,
+            Some("g") => true,
+            Some(_) => {
+                self.set_status("only /g can follow old/new");
+                return;
+            },
+        };
+        if old.is_empty() {
+            self.set_status("type old/new");
+            return;
+        }
+        if let Err(message) = edit_state.replace(old.as_bytes(), new.as_bytes(), all) {
+            self.set_status(message);
+            return;
+        }
+        self.set_window_mode(window, KWindowMode::Editing(edit_state));
+        self.close_prompt(window, false);
+    }
+
+    // Jumps an editing window to the line number typed in the filebar and
+    // hands the keyboard back to it. A line the file doesn't have is refused.
+    fn try_go_to_line(&mut self, window: KWindows) {
+        let KWindowMode::Editing(mut edit_state) = self.get_window_mode(window) else { return };
+        let (len, bytes) = self.filebar_buffer.get_bytes();
+        let line = str::from_utf8(&bytes[..len]).ok().and_then(|typed| typed.parse().ok());
+        match line {
+            Some(line) if edit_state.go_to_line(line) => {
+                self.set_window_mode(window, KWindowMode::Editing(edit_state));
+                self.close_prompt(window, false);
+            },
+            _ => self.set_status("no such line"),
+        }
+    }
+
+    // Reports a file that couldn't be opened. The operation that wanted it is
+    // abandoned rather than unwrapped, since running out of descriptors is
+    // expected with four windows at work.
+    fn report_open_error(&mut self, error: FileSystemError) {
+        self.set_status(open_error_status(error));
+    }
+
+    // Reports a write that didn't go through; the file written is left
+    // partial or empty.
+    fn report_write_error(&mut self, error: FileSystemError) {
+        self.set_status(write_error_status(error));
+    }
+
+    fn get_window_mode(&self, window: KWindows) -> KWindowMode {
+        match window {
+            KWindows::F1 => self.window_modes[0],
+            KWindows::F2 => self.window_modes[1],
+            KWindows::F3 => self.window_modes[2],
+            KWindows::F4 => self.window_modes[3],
+        }
+    }
+
+    // An editing buffer always wraps to the window it is shown in.
+    fn set_window_mode(&mut self, window: KWindows, mut mode: KWindowMode) {
+        let frame = self.frame(window);
+        match &mut mode {
+            KWindowMode::Editing(edit_state) => {
+                if (edit_state.width, edit_state.height) != (frame.width, frame.height) {
+                    edit_state.width = frame.width;
+                    edit_state.height = frame.height;
+                    edit_state.scroll_to_cursor();
+                }
+            },
+            KWindowMode::Directory(dir_state) => {
+                if dir_state.height != frame.height {
+                    dir_state.height = frame.height;
+                    dir_state.scroll_to_cursor();
+                }
+            },
+            KWindowMode::Running(run_state) => run_state.height = frame.height,
+            _ => {},
+        }
+        self.window_modes[window.index()] = mode;
+    }
+
+    // Returns every window to the top of its directory listing and clears
+    // the per-window counters.
+    fn reset_all(&mut self) {
+        if !self.guard_unsaved(&[KWindows::F1, KWindows::F2, KWindows::F3, KWindows::F4]) { return }
+        self.window_modes = [KWindowMode::directory(0); NUM_WINDOWS];
+        // A maximized window's new listing still needs its larger frame.
+        self.maximize(self.maximized);
+        self.run_counts = [0; NUM_WINDOWS];
+        self.last_runs = [None; NUM_WINDOWS];
+        self.headless_steps = [None; NUM_WINDOWS];
+        self.prompts = PromptStack::new();
+        self.filebar_buffer.clear();
+        self.text_buffer.clear();
+        self.filebar_use = FilebarUse::Create;
+        self.selected = KSelection::Window(KWindows::F1);
+        self.previous_selected = KSelection::Filebar;
+        self.set_status("all windows reset");
+    }
+
+    // Arrow keys pick files in a directory window, move the cursor in an
+    // editing one, and pan unwrapped output in a running one.
+    fn move_cursor(&mut self, direction: Direction) {
+        let KSelection::Window(window) = self.selected else { return };
+        match (self.get_window_mode(window), direction) {
+            (KWindowMode::Editing(mut edit_state), direction) => {
+                edit_state.move_cursor(direction);
+                self.set_window_mode(window, KWindowMode::Editing(edit_state));
+            },
+            (KWindowMode::Directory(_), Direction::Up) => self.move_dir_cursor(-(DIRECTORY_COLUMNS as isize)),
+            (KWindowMode::Directory(_), Direction::Down) => self.move_dir_cursor(DIRECTORY_COLUMNS as isize),
+            (KWindowMode::Directory(_), Direction::Left) => self.move_dir_cursor(-1),
+            (KWindowMode::Directory(_), Direction::Right) => self.move_dir_cursor(1),
+            (KWindowMode::Running(mut run_state), Direction::Left | Direction::Right) if !run_state.wrap_output => {
+                run_state.pan(if direction == Direction::Left { -1 } else { 1 });
+                self.set_window_mode(window, KWindowMode::Running(run_state));
+            },
+            _ => {},
+        }
+    }
+
+    fn move_dir_cursor(&mut self, delta: isize) {
+        if let KSelection::Window(window) = self.selected {
+            if let KWindowMode::Directory(mut dir_state) = self.get_window_mode(window) {
+                let (file_count, _) = self.list_directory();
+                dir_state.move_cursor(delta, file_count);
+                self.set_window_mode(window, KWindowMode::Directory(dir_state));
+            }
+        }
+    }
+
+    // F7 scrolls up and F8 down, through the text of an editing window or
+    // the scrollback of a running one.
+    fn scroll_window(&mut self, delta: isize) {
+        if let KSelection::Window(window) = self.selected {
+            match self.get_window_mode(window) {
+                KWindowMode::Editing(mut edit_state) => {
+                    edit_state.scroll = edit_state.scroll.saturating_add_signed(delta);
+                    let line_count = edit_state.line_count(edit_state.width);
+                    if edit_state.scroll >= line_count {
+                        edit_state.scroll = line_count - 1;
+                    }
+                    self.set_window_mode(window, KWindowMode::Editing(edit_state));
+                },
+                KWindowMode::Running(mut run_state) => {
+                    run_state.scroll_by(-delta);
+                    self.set_window_mode(window, KWindowMode::Running(run_state));
+                },
+                _ => {},
+            }
+        }
+    }
+
+    // Opens the file under a directory window's cursor for reading. If it was
+    // deleted since the listing was drawn, the cursor is clamped to the files
+    // that remain and the window stays in directory mode.
+    fn open_selected_file(
+        &mut self,
+        window: KWindows,
+        chosen_file: usize,
+    ) -> Option<(usize, [u8; MAX_FILENAME_BYTES])> {
+        let (file_count, directory) = self.list_directory();
+        let opened = if chosen_file < file_count {
+            self.fs.open_read(filename_str(&directory[chosen_file]))
+        } else {
+            Err(FileSystemError::FileNotFound)
+        };
+        match opened {
+            Err(FileSystemError::FileNotFound) => {
+                // The listing the cursor was on is out of date.
+                self.files_changed();
+                let (file_count, _) = self.list_directory();
+                let cursor = chosen_file.min(file_count.saturating_sub(1));
+                self.set_window_mode(window, KWindowMode::directory(cursor));
+                self.report_open_error(FileSystemError::FileNotFound);
+                None
+            },
+            Err(error) => {
+                self.report_open_error(error);
+                None
+            },
+            Ok(file) => Some((file, directory[chosen_file])),
+        }
+    }
+
+    // A file already open in another editing window is refused: the two
+    // buffers would each overwrite the other's changes when saved.
+    fn switch_to_edit_mode(&mut self, window: KWindows) {
+        if let KWindowMode::Directory(dir_state) = self.get_window_mode(window) {
+            let chosen_file = dir_state.cursor;
+            let (file_count, directory) = self.list_directory();
+            if chosen_file < file_count {
+                let editing_elsewhere = [KWindows::F1, KWindows::F2, KWindows::F3, KWindows::F4].into_iter().find(|&other| {
+                    other != window && matches!(
+                        self.get_window_mode(other),
+                        KWindowMode::Editing(edit_state) if edit_state.filename == directory[chosen_file]
+                    )
+                });
+                if let Some(other) = editing_elsewhere {
+                    self.set_status(match other {
+                        KWindows::F1 => "already being edited in F1",
+                        KWindows::F2 => "already being edited in F2",
+                        KWindows::F3 => "already being edited in F3",
+                        KWindows::F4 => "already being edited in F4",
+                    });
+                    return;
+                }
+            }
+            let Some((file, filename)) = self.open_selected_file(window, chosen_file) else { return };
+            let mut buffer = [0u8; PRACTICAL_FILE_BUFFER_SIZE];
+            let read = self.fs.read(file, &mut buffer);
+            // Saving a partially loaded file would silently drop its tail.
+            let truncated = read == Ok(PRACTICAL_FILE_BUFFER_SIZE)
+                && matches!(self.fs.read(file, &mut [0u8; 1]), Ok(len) if len > 0);
+            self.fs.close(file).ok();
+            let Ok(filesize) = read else {
+                self.set_status("couldn't read file");
+                return;
+            };
+            if truncated {
+                self.set_status("file too long to edit");
+                return;
+            }
+            self.set_window_mode(
+                window,
+                KWindowMode::editing(filename, buffer, filesize, chosen_file),
+            );
+        }
+    }
+
+    fn switch_to_directory_mode(&mut self, window: KWindows) {
+        if let KWindowMode::SysInfo | KWindowMode::Scratch(_) = self.get_window_mode(window) {
+            self.set_window_mode(window, KWindowMode::directory(0));
+        }
+        if let KWindowMode::Running(run_state) = self.get_window_mode(window) {
+            self.last_runs[window.index()] = Some(LastRun {
+                filename: run_state.filename,
+                program: run_state.program,
+                program_len: run_state.program_len,
+                output: run_state.output,
+                instructions_run: run_state.instructions_run,
+                error: run_state.error,
+            });
+            self.set_window_mode(window, KWindowMode::directory(0));
+        }
+        if let KWindowMode::Editing(edit_state) = self.get_window_mode(window) {
+            if !self.save_editing_file(window) { return }
+            self.set_window_mode(
+                window,
+                KWindowMode::directory(edit_state.directory_index),
+            );
+        }
+    }
+
+    // Deletes the file under a directory window's cursor once `d` is pressed
+    // twice in a row, then clamps the cursor to the files that remain.
+    fn delete_selected_file(&mut self, window: KWindows) {
+        let KWindowMode::Directory(mut dir_state) = self.get_window_mode(window) else { return };
+        let (file_count, directory) = self.list_directory();
+        if dir_state.cursor >= file_count { return }
+        if !self.confirm("delete this file? d again") { return }
+        let deleted = directory[dir_state.cursor];
+        match self.fs.delete(filename_str(&deleted)) {
+            Ok(()) => {
+                self.files_changed();
+                for mode in self.window_modes.iter_mut() {
+                    if let KWindowMode::Editing(edit_state) = mode {
+                        if filename_str(&edit_state.filename) == filename_str(&deleted) {
+                            edit_state.detached = true;
+                        }
+                    }
+                }
+                let (file_count, _) = self.list_directory();
+                dir_state.cursor = dir_state.cursor.min(file_count.saturating_sub(1));
+                dir_state.scroll_to_cursor();
+                self.set_window_mode(window, KWindowMode::Directory(dir_state));
+                self.set_status("file deleted");
+            },
+            Err(error) => self.report_open_error(error),
+        }
+    }
+
+    // Brings back the output of the run this window last left, stopped so
+    // only its output is shown. The interpreter isn't kept, but the source
+    // is, so `r` reruns it from the start.
+    fn show_last_output(&mut self, window: KWindows) {
+        let Some(last_run) = self.last_runs[window.index()] else {
+            self.set_status("no earlier output in this window");
+            return;
+        };
+        let program = str::from_utf8(&last_run.program[..last_run.program_len]).unwrap();
+        let KWindowMode::Running(mut run_state) = KWindowMode::running(last_run.filename, program, false) else { return };
+        run_state.output = last_run.output;
+        run_state.instructions_run = last_run.instructions_run;
+        run_state.error = last_run.error;
+        run_state.status = ProcessStatus::Finished;
+        self.set_window_mode(window, KWindowMode::Running(run_state));
+    }
+
+    // Returns false, leaving the buffer dirty, if the file couldn't be opened.
+    // `open_create` empties an existing file, so a shortened buffer leaves no
+    // stale tail on disk.
+    fn save_editing_file(&mut self, window: KWindows) -> bool {
+        if let KWindowMode::Editing(mut edit_state) = self.get_window_mode(window) {
+            let filename_str = filename_str(&edit_state.filename);
+            if edit_state.detached
+                && self.find_file(filename_str).is_some()
+                && !self.confirm("name taken! save again to overwrite")
+            {
+                return false;
+            }
+            let file = match self.fs.open_create(filename_str) {
+                Ok(file) => file,
+                Err(error) => {
+                    self.report_open_error(error);
+                    return false;
+                }
+            };
+            let written = self.fs.write(file, edit_state.contents());
+            let closed = self.fs.close(file);
+            self.files_changed();
+            // The file on disk is now partial, but the buffer stays dirty and
+            // intact, so it can be trimmed and saved again.
+            if let Err(error) = written.and(closed) {
+                self.report_write_error(error);
+                return false;
+            }
+            edit_state.dirty = false;
+            edit_state.detached = false;
+            self.set_window_mode(window, KWindowMode::Editing(edit_state));
+        }
+        true
+    }
+
+    // Asked before resetting `windows`, the one transition that throws away
+    // their editing state. The others keep it: F6 saves before leaving, a swap
+    // moves the buffer, a rename follows it and a delete detaches it. Under
+    // `UnsavedPolicy::AutoSave` dirty buffers are saved and the reset goes
+    // ahead; under `Warn` the first attempt only warns, and repeating it with
+    // the very next key discards the edits.
+    fn guard_unsaved(&mut self, windows: &[KWindows]) -> bool {
+        let dirty = windows.iter().any(|window| {
+            matches!(self.window_modes[window.index()], KWindowMode::Editing(edit_state) if edit_state.dirty)
+        });
+        if !dirty { return true }
+        match self.unsaved_policy {
+            // Clean buffers aren't written back, so a file changed since one
+            // was opened isn't overwritten with the older text.
+            UnsavedPolicy::AutoSave => windows.iter().all(|&window| {
+                !matches!(self.window_modes[window.index()], KWindowMode::Editing(edit_state) if edit_state.dirty)
+                    || self.save_editing_file(window)
+            }),
+            UnsavedPolicy::Warn => self.confirm("unsaved edits! repeat to discard"),
+        }
+    }
+
+    // Asks before doing something that can't be undone. The first time this
+    // shows `warning` and returns false; it returns true only when the very
+    // next key repeats the same request.
+    fn confirm(&mut self, warning: &'static str) -> bool {
+        if self.confirmed == Some(warning) { return true }
+        self.confirm_armed = Some(warning);
+        self.set_status(warning);
+        false
+    }
+
+    // Compares an editing window's buffer with the file on disk and shows the
+    // result for confirmation. Returns false if the window isn't editing, and
+    // true without a review if the file on disk couldn't be opened.
+    fn begin_save_review(&mut self, window: KWindows) -> bool {
+        let KWindowMode::Editing(mut edit_state) = self.get_window_mode(window) else { return false };
+        let mut on_disk = [0u8; PRACTICAL_FILE_BUFFER_SIZE];
+        let mut on_disk_len = 0;
+        match self.fs.open_read(filename_str(&edit_state.filename)) {
+            Ok(file) => {
+                on_disk_len = self.fs.read(file, &mut on_disk).unwrap_or(0);
+                self.fs.close(file).ok();
+            },
+            Err(FileSystemError::FileNotFound) => {},
+            Err(error) => {
+                self.report_open_error(error);
+                return true;
+            },
+        }
+        if !self.open_prompt(window) { return true }
+        edit_state.save_review = Some(SaveReview::compare(&on_disk[..on_disk_len], edit_state.contents()));
+        self.set_window_mode(window, KWindowMode::Editing(edit_state));
+        true
+    }
+
+    // Every modal prompt opens with this before it changes the selection or
+    // the window's state, and ends with `close_prompt`.
+    // A window has at most one prompt open; returns false, opening nothing,
+    // if `window` already has one.
+    fn open_prompt(&mut self, window: KWindows) -> bool {
+        if self.prompts.has(window) { return false }
+        self.prompts.push(PromptReturn { selected: self.selected, window });
+        true
+    }
+
+    // Puts the selection back where it was when the window's innermost prompt
+    // opened. A cancelled prompt also takes down whatever it showed in its
+    // window; a completed one leaves the window's state to whatever the
+    // prompt did.
+    fn close_prompt(&mut self, window: KWindows, cancelled: bool) {
+        let Some(entry) = self.prompts.pop(window) else { return };
+        if self.filebar_use.owner() == Some(window) {
+            self.filebar_use = FilebarUse::Create;
+            self.filebar_buffer.clear();
+            self.text_buffer.clear();
+        }
+        self.selected = entry.selected;
+        if cancelled {
+            if let KWindowMode::Editing(mut edit_state) = self.get_window_mode(entry.window) {
+                edit_state.save_review = None;
+                edit_state.reindent_prompt = false;
+                self.set_window_mode(entry.window, KWindowMode::Editing(edit_state));
+            }
+        }
+    }
+
+    fn toggle_sys_info(&mut self, window: KWindows) {
+        match self.get_window_mode(window) {
+            KWindowMode::Directory(_) => self.set_window_mode(window, KWindowMode::SysInfo),
+            KWindowMode::SysInfo => self.set_window_mode(window, KWindowMode::directory(0)),
+            _ => {},
+        }
+    }
+
+    // Estimated from file sizes, since the filesystem doesn't report its free
+    // blocks; inode and directory blocks aren't counted.
+    fn blocks_used(&mut self) -> usize {
+        let directory = self.directory();
+        directory.sizes[..directory.file_count].iter()
+            .map(|size| size.div_ceil(BLOCK_SIZE))
+            .sum()
+    }
+
+    fn list_directory(&mut self) -> (usize, [[u8; MAX_FILENAME_BYTES]; MAX_FILES_STORED]) {
+        let directory = self.directory();
+        (directory.file_count, directory.names)
+    }
+
+    fn directory(&mut self) -> DirectoryCache {
+        if let Some(directory) = self.directory_cache {
+            return directory;
+        }
+        let (file_count, names) = self.fs.list_directory().unwrap();
+        let mut sizes = [0; MAX_FILES_STORED];
+        for (size, name) in sizes.iter_mut().zip(names.iter()).take(file_count) {
+            *size = self.file_size(filename_str(name)).unwrap_or(0);
+        }
+        let directory = DirectoryCache { file_count, names, sizes };
+        self.directory_cache = Some(directory);
+        directory
+    }
+
+    // Every change to the files on disk lands here so that the next draw
+    // reads the directory afresh.
+    fn files_changed(&mut self) {
+        self.directory_cache = None;
+    }
+
+    fn find_file(&mut self, filename: &str) -> Option<usize> {
+        let (file_count, directory) = self.list_directory();
+        directory[..file_count].iter().position(|name| {
+            name.split(|&byte| byte == 0).next() == Some(filename.as_bytes())
+        })
+    }
+
+    fn file_size(&mut self, filename: &str) -> Option<usize> {
+        let file = self.fs.open_read(filename).ok()?;
+        let mut buffer = [0u8; PRACTICAL_FILE_BUFFER_SIZE];
+        let size = self.fs.read(file, &mut buffer).ok();
+        self.fs.close(file).ok()?;
+        size
+    }
+
+    // Reads the program under a directory window's cursor, refusing files
+    // that can't be read or aren't program text.
+    fn read_selected_program(
+        &mut self,
+        window: KWindows,
+        chosen_file: usize,
+    ) -> Option<([u8; MAX_FILENAME_BYTES], [u8; PRACTICAL_FILE_BUFFER_SIZE], usize)> {
+        let (file, filename) = self.open_selected_file(window, chosen_file)?;
+        let (buffer, filesize) = self.read_program(file)?;
+        Some((filename, buffer, filesize))
+    }
+
+    // Reads an open program file whole and closes it.
+    fn read_program(&mut self, file: usize) -> Option<([u8; PRACTICAL_FILE_BUFFER_SIZE], usize)> {
+        let mut buffer = [0u8; PRACTICAL_FILE_BUFFER_SIZE];
+        let read = self.fs.read(file, &mut buffer);
+        self.fs.close(file).ok();
+        let Ok(filesize) = read else {
+            self.set_status("couldn't read file");
+            return None;
+        };
+        let contents = &buffer[..filesize];
+        if str::from_utf8(contents).is_err() || looks_binary(contents) {
+            self.set_status("not a text program");
+            return None;
+        }
+        Some((buffer, filesize))
+    }
+
+    fn switch_to_run_mode(&mut self, window: KWindows, step_mode: bool) {
+        if let KWindowMode::Directory(dir_state) = self.get_window_mode(window) {
+            let Some((filename, buffer, filesize)) = self.read_selected_program(window, dir_state.cursor) else { return };
+            let program = str::from_utf8(&buffer[..filesize]).unwrap();
+            self.run_counts[window.index()] += 1;
+            self.set_window_mode(
+                window,
+                KWindowMode::running(filename, program, step_mode),
+            );
+        }
+    }
+
+    // Runs the selected program headlessly and saves what it prints next to
+    // it, e.g. `pi` -> `pi.out`. Long names are shortened to fit the suffix,
+    // so the output name can land on another file: on the source itself it
+    // is refused, and any other file is only overwritten once confirmed.
+    fn run_to_output_file(&mut self, window: KWindows) {
+        if let KWindowMode::Directory(dir_state) = self.get_window_mode(window) {
+            let (file_count, directory) = self.list_directory();
+            if dir_state.cursor >= file_count { return }
+            let (name_len, output_name) = output_filename(&directory[dir_state.cursor]);
+            let output_name_str = str::from_utf8(&output_name[..name_len]).unwrap();
+            if output_name_str == filename_str(&directory[dir_state.cursor]) {
+                self.set_status("output would overwrite the source");
+                return;
+            }
+            if self.find_file(output_name_str).is_some()
+                && !self.confirm("output file exists! x to overwrite")
+            {
+                return;
+            }
+            let Some((_, buffer, filesize)) = self.read_selected_program(window, dir_state.cursor) else { return };
+            let program = str::from_utf8(&buffer[..filesize]).unwrap();
+            let output = match run_headless(program, &[]) {
+                Ok(output) => output,
+                Err(message) => {
+                    self.set_status(message);
+                    return;
+                }
+            };
+            let file = match self.fs.open_create(output_name_str) {
+                Ok(file) => file,
+                Err(error) => {
+                    self.report_open_error(error);
+                    return;
+                }
+            };
+            let written = self.fs.write(file, &output.bytes[..output.len]);
+            let closed = self.fs.close(file);
+            self.files_changed();
+            if let Err(error) = written.and(closed) {
+                self.fs.delete(output_name_str).ok();
+                self.report_write_error(error);
+                return;
+            }
+            self.headless_steps[window.index()] = Some(output.steps);
+            self.set_status(if output.truncated {
+                "output cut short to fit the file"
+            } else {
+                "output saved to .out file"
+            });
+        }
+    }
+}
+
+// `>` running, `|` paused, `?` waiting for input, `.` finished, `!` halted on
+// an error.
+fn run_state_glyph(run_state: &RunningState) -> char {
+    match run_state.status {
+        _ if run_state.error.is_some() => '!',
+        ProcessStatus::Finished => '.',
+        ProcessStatus::AwaitingInput => '?',
+        ProcessStatus::Runnable if run_state.paused || run_state.more_prompt => '|',
+        ProcessStatus::Runnable => '>',
+    }
+}
+
+// Names are kept to letters, digits, `_` and `.` (for `.out` files), so they
+// read cleanly in the directory columns.
+fn check_filename(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() { return Err("type a file name") }
+    if name.len() > MAX_FILENAME_BYTES { return Err("file name too long") }
+    if !name.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'.') {
+        return Err("names use letters, digits, _ and .");
+    }
+    Ok(())
+}
+
+// The status shown when a file can't be opened.
+fn open_error_status(error: FileSystemError) -> &'static str {
+    match error {
+        FileSystemError::TooManyOpen => "too many open files",
+        FileSystemError::FileNotFound => "file no longer exists",
+        FileSystemError::TooManyFiles => "directory is full",
+        FileSystemError::DiskFull => "disk is full",
+        FileSystemError::AlreadyOpen => "file is in use",
+        _ => "couldn't open file",
+    }
+}
+
+// The status shown when a write doesn't go through.
+fn write_error_status(error: FileSystemError) -> &'static str {
+    match error {
+        FileSystemError::DiskFull => "disk full! file not saved",
+        FileSystemError::FileTooBig => "file too big! not saved",
+        _ => "couldn't write file",
+    }
+}
+
+// A filename as stored in a fixed-size array, without its NUL padding.
+fn filename_str(name: &[u8]) -> &str {
+    let len = name.iter().position(|&byte| byte == 0).unwrap_or(name.len());
+    str::from_utf8(&name[..len]).unwrap()
+}
+
+fn output_filename(source: &[u8; MAX_FILENAME_BYTES]) -> (usize, [u8; MAX_FILENAME_BYTES]) {
+    let source_len = source.iter().position(|&byte| byte == 0).unwrap_or(MAX_FILENAME_BYTES);
+    let stem_len = source_len.min(MAX_FILENAME_BYTES - OUTPUT_FILE_SUFFIX.len());
+    let mut name = [0u8; MAX_FILENAME_BYTES];
+    name[..stem_len].copy_from_slice(&source[..stem_len]);
+    name[stem_len..stem_len + OUTPUT_FILE_SUFFIX.len()].copy_from_slice(OUTPUT_FILE_SUFFIX);
+    (stem_len + OUTPUT_FILE_SUFFIX.len(), name)
+}
+
+// The one place typed characters become bytes. Only printable ASCII, plus
+// newline where the caller allows it, gets through; anything else would be
+// mangled by a `c as u8` truncation on its way to disk or the screen.
+fn input_byte(c: char, allow_newline: bool) -> Option<u8> {
+    match c {
+        ' '..='~' => Some(c as u8),
+        '\n' if allow_newline => Some(b'\n'),
+        _ => None,
+    }
+}
+
+// Heuristic for files that are clearly not program text: too many bytes that
+// are neither drawable nor ordinary whitespace.
+fn looks_binary(contents: &[u8]) -> bool {
+    let undrawable = contents.iter()
+        .filter(|&&byte| !is_drawable(byte as char) && !matches!(byte, b'\n' | b'\t' | b'\r'))
+        .count();
+    undrawable * 100 > contents.len() * MAX_BINARY_PERCENT
+}
+
+// Shows the byte under the editing cursor as hex and decimal, e.g. "0x41 65",
+// ending just before `end_col`.
+fn draw_cursor_byte(edit_state: &EditingState, end_col: usize, row: usize, color: ColorCode) {
+    match edit_state.byte_at_cursor() {
+        Some(byte) => {
+            let hex = [b'0', b'x', hex_digit(byte >> 4), hex_digit(byte & 0xf)];
+            let value_col = end_col - hex.len() - 1 - num_digits(byte as usize);
+            plot_str(str::from_utf8(&hex).unwrap(), value_col, row, color);
+            plot_num(byte as isize, value_col + hex.len() + 1, row, color);
+        }
+        None => {
+            plot_str("EOF", end_col - 3, row, color);
+        }
+    }
+}
+
+fn draw_edit_text(edit_state: &EditingState, col: usize, row: usize, color: ColorCode) {
+    for line in 0..edit_state.height {
+        if let Some(line_bytes) = edit_state.read_line(edit_state.scroll + line) {
+            let colors = syntax_colors(&line_bytes, color);
+            for (i, &byte) in line_bytes.iter().take(edit_state.width).enumerate() {
+                plot_bytes_clamped(&[byte], col + 1 + i, row + 1 + line, 1, colors[i]);
+            }
+        } else {
+            continue
+        }
+    }
+    if let Some(found) = edit_state.found {
+        let visible = edit_state.scroll..edit_state.scroll + edit_state.height;
+        edit_state.walk_display(edit_state.width, |line, line_col, index, byte| {
+            if (found..found + edit_state.search_len).contains(&index) && byte != b'\n' && visible.contains(&line) {
+                plot_bytes_clamped(&[byte], col + 1 + line_col, row + 1 + line - edit_state.scroll, 1, highlight_color());
+            }
+        });
+    }
+    // The cursor goes on top of the text, over whatever byte it is before.
+    let (cursor_line, cursor_col) = edit_state.cursor_position();
+    if (edit_state.scroll..edit_state.scroll + edit_state.height).contains(&cursor_line) {
+        let under_cursor = edit_state.read_line(cursor_line).map_or(b' ', |line_bytes| line_bytes[cursor_col]);
+        plot_bytes_clamped(&[under_cursor], col + 1 + cursor_col, row + 1 + cursor_line - edit_state.scroll, 1, highlight_color());
+    }
+    if edit_state.reindent_prompt {
+        plot_str_clamped(REINDENT_PROMPT, col + FILENAME_LABEL_COL_OFFSET, row + edit_state.height + 1, edit_state.width, highlight_color());
+    }
+}
+
+// Colors for one displayed line of program text: keywords, string literals,
+// and numbers stand out from `color`. Each line is scanned on its own, so a
+// token cut by a wrap, or a string carried over from the line above, just
+// draws in `color`.
+fn syntax_colors(line: &[u8; MAX_WINDOW_WIDTH], color: ColorCode) -> [ColorCode; MAX_WINDOW_WIDTH] {
+    let mut colors = [color; MAX_WINDOW_WIDTH];
+    let mut i = 0;
+    while i < MAX_WINDOW_WIDTH {
+        let start = i;
+        if line[i] == b'"' {
+            i += 1;
+            while i < MAX_WINDOW_WIDTH && line[i] != b'"' { i += 1 }
+            if i < MAX_WINDOW_WIDTH {
+                i += 1;
+                colors[start..i].fill(string_color());
+            }
+        } else if line[i].is_ascii_digit() {
+            while i < MAX_WINDOW_WIDTH && line[i].is_ascii_digit() { i += 1 }
+            colors[start..i].fill(number_color());
+        } else if line[i].is_ascii_alphabetic() || line[i] == b'_' {
+            while i < MAX_WINDOW_WIDTH && (line[i].is_ascii_alphanumeric() || line[i] == b'_') { i += 1 }
+            if SYNTAX_KEYWORDS.contains(&&line[start..i]) {
+                colors[start..i].fill(keyword_color());
+            }
+        } else {
+            i += 1;
+        }
+    }
+    colors
+}
+
+// Draws a running window: the filename and any pause on its top border, the
+// pager prompt or finished footer on its bottom border, and its output.
+fn draw_running_window(run_state: &RunningState, frame: Frame, color: ColorCode) {
+    let (col, row) = (frame.col, frame.row);
+    plot_bytes_clamped(&run_state.filename, col + FILENAME_LABEL_COL_OFFSET, row, MAX_FILENAME_BYTES, color);
+    if run_state.paused {
+        plot_str_clamped(
+            if run_state.pause_on_output { PAUSED_ON_OUTPUT_HEADER } else { PAUSED_HEADER },
+            col + RUN_STATE_LABEL_COL_OFFSET,
+            row,
+            frame.label_col_offset() - RUN_STATE_LABEL_COL_OFFSET,
+            highlight_color(),
+        );
+    }
+    if run_state.more_prompt {
+        plot_str_clamped(
+            MORE_PROMPT,
+            col + FILENAME_LABEL_COL_OFFSET,
+            row + frame.height + 1,
+            frame.width,
+            highlight_color(),
+        );
+    } else if run_state.status == ProcessStatus::Finished {
+        let footer_row = row + frame.height + 1;
+        let footer_col = col + FILENAME_LABEL_COL_OFFSET;
+        let label = if run_state.error.is_some() { HALTED_LABEL } else { FINISHED_LABEL };
+        plot_str(label, footer_col, footer_row, color);
+        plot_num(run_state.instructions_run as isize, footer_col + label.len(), footer_row, color);
+        plot_str(
+            STEPS_LABEL,
+            footer_col + label.len() + num_digits(run_state.instructions_run),
+            footer_row,
+            color,
+        );
+    }
+    draw_output(run_state, frame, color);
+}
+
+// Draws a running window's output bottom-up, starting `scroll` lines back
+// from the newest. Wrapped lines continue under their first row; unwrapped
+// lines are cut to the window and panned by `hscroll`. A program waiting for
+// input gets the bottom row for the line being typed, with a caret after it,
+// and its prompt is highlighted.
+fn draw_output(run_state: &RunningState, frame: Frame, color: ColorCode) {
+    let Frame { col, row, width, height } = frame;
+    let output = &run_state.output;
+    let mut rows_left = height;
+    if run_state.status == ProcessStatus::AwaitingInput {
+        rows_left -= 1;
+        let input_row = row + height;
+        plot_str(INPUT_PROMPT, col + 1, input_row, highlight_color());
+        let input_width = width - INPUT_PROMPT.len();
+        plot_bytes_clamped(
+            &run_state.input[run_state.input_len.saturating_sub(input_width - 1)..run_state.input_len],
+            col + 1 + INPUT_PROMPT.len(),
+            input_row,
+            input_width - 1,
+            color,
+        );
+        let caret_col = col + 1 + INPUT_PROMPT.len() + run_state.input_len.min(input_width - 1);
+        plot(INPUT_CARET, caret_col, input_row, highlight_color());
+    }
+    if let Some(error) = &run_state.error {
+        rows_left -= 1;
+        plot_bytes_clamped(&error.bytes[..error.len], col + 1, row + height, width, error_color());
+    }
+    let prompt_line = if run_state.status == ProcessStatus::AwaitingInput && run_state.prompted {
+        output.count.checked_sub(1)
+    } else {
+        None
+    };
+    for line in (0..output.count.saturating_sub(run_state.scroll)).rev() {
+        if rows_left == 0 { break }
+        let tick = output.ticks[line];
+        let prefix = if run_state.show_ticks { num_digits(tick) + 1 } else { 0 };
+        let line_width = width.saturating_sub(prefix).max(1);
+        let bytes = if run_state.wrap_output {
+            output.line(line)
+        } else {
+            &output.line(line)[run_state.hscroll.min(output.lens[line])..]
+        };
+        let rows_needed = if run_state.wrap_output { bytes.len().div_ceil(line_width).max(1) } else { 1 };
+        let line_color = if prompt_line == Some(line) { highlight_color() } else { color };
+        for chunk in 0..rows_needed {
+            let Some(target) = (rows_left + chunk).checked_sub(rows_needed) else { continue };
+            let line_row = row + 1 + target;
+            if chunk == 0 && run_state.show_ticks {
+                plot_num(tick as isize, col + 1, line_row, color);
+            }
+            let start = (chunk * line_width).min(bytes.len());
+            plot_bytes_clamped(&bytes[start..], col + 1 + prefix, line_row, line_width, line_color);
+        }
+        rows_left = rows_left.saturating_sub(rows_needed);
+    }
+}
+
+// Lists the scratch history, each line under a prompt with its result below
+// it, and then the line being typed.
+fn draw_scratch(scratch: &ScratchState, frame: Frame, color: ColorCode) {
+    let (col, row, width) = (frame.col + 1, frame.row + 1, frame.width);
+    for (i, entry) in scratch.history[..scratch.history_count].iter().enumerate() {
+        plot_str(SCRATCH_PROMPT, col, row + 2 * i, color);
+        plot_bytes_clamped(&entry.line[..entry.line_len], col + SCRATCH_PROMPT.len(), row + 2 * i, width - SCRATCH_PROMPT.len(), color);
+        plot_bytes_clamped(&entry.result[..entry.result_len], col, row + 2 * i + 1, width, color);
+    }
+    let input_row = row + 2 * scratch.history_count;
+    plot_str(SCRATCH_PROMPT, col, input_row, highlight_color());
+    plot_bytes_clamped(&scratch.input[..scratch.input_len], col + SCRATCH_PROMPT.len(), input_row, width - SCRATCH_PROMPT.len(), color);
+}
+
+// Lists a save review: the prompt, a "~changed +added -removed" summary, and
+// one "~ line 12" row per listed line.
+fn draw_save_review(review: &SaveReview, frame: Frame, color: ColorCode) {
+    let (col, row) = (frame.col + 1, frame.row + 1);
+    plot_str_clamped(REVIEW_PROMPT, col, row, frame.width, color);
+    let mut summary_col = col;
+    for change in [LineChange::Modified, LineChange::Added, LineChange::Removed] {
+        let count = review.counts[change as usize];
+        plot(change.marker(), summary_col, row + 1, color);
+        plot_num(count as isize, summary_col + 1, row + 1, color);
+        summary_col += num_digits(count) + 3;
+    }
+    for (i, &(change, line)) in review.entries[..review.entry_count].iter().enumerate() {
+        plot(change.marker(), col, row + 2 + i, color);
+        plot_str("line ", col + 2, row + 2 + i, color);
+        plot_num(line as isize, col + 7, row + 2 + i, color);
+    }
+}
+
+fn num_digits(mut value: usize) -> usize {
+    let mut digits = 1;
+    while value >= 10 {
+        value /= 10;
+        digits += 1;
+    }
+    digits
+}
+
+fn hex_digit(nibble: u8) -> u8 {
+    match nibble {
+        0..=9 => b'0' + nibble,
+        _ => b'a' + nibble - 10,
+    }
+}
+
+// Drawing goes to `pending` first, and `present` copies to the VGA buffer
+// only the cells that differ from what it last put there. Areas blanked and
+// redrawn within one frame then never flash, and unchanged cells cost nothing.
+struct Screen {
+    pending: [[Option<(char, ColorCode)>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    shown: [[Option<(char, ColorCode)>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+}
+
+static SCREEN: spin::Mutex<Screen> = spin::Mutex::new(Screen {
+    pending: [[None; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    shown: [[None; BUFFER_WIDTH]; BUFFER_HEIGHT],
+});
+
+fn present() {
+    let mut screen = SCREEN.lock();
+    let Screen { pending, shown } = &mut *screen;
+    for (row, (pending_row, shown_row)) in pending.iter().zip(shown.iter_mut()).enumerate() {
+        for (col, (&cell, shown_cell)) in pending_row.iter().zip(shown_row.iter_mut()).enumerate() {
+            if let Some((c, color)) = cell {
+                if *shown_cell != cell {
+                    vga_buffer::plot(c, col, row, color);
+                    *shown_cell = cell;
+                }
+            }
+        }
+    }
+}
+
+// These stand in for the VGA buffer's own, drawing into `SCREEN` for the next
+// `present`. Anything past the edge of the screen is dropped.
+fn plot(c: char, col: usize, row: usize, color: ColorCode) {
+    if col < BUFFER_WIDTH && row < BUFFER_HEIGHT {
+        SCREEN.lock().pending[row][col] = Some((c, color));
+    }
+}
+
+fn plot_str(s: &str, col: usize, row: usize, color: ColorCode) {
+    for (i, c) in s.chars().enumerate() {
+        plot(c, col + i, row, color);
+    }
+}
+
+fn plot_num(num: isize, col: usize, row: usize, color: ColorCode) {
+    let mut digits = [0u8; 20];
+    let mut len = 0;
+    let mut rest = num.unsigned_abs();
+    loop {
+        digits[len] = b'0' + (rest % 10) as u8;
+        len += 1;
+        rest /= 10;
+        if rest == 0 { break }
+    }
+    let start = if num < 0 {
+        plot('-', col, row, color);
+        col + 1
+    } else {
+        col
+    };
+    for (i, &digit) in digits[..len].iter().rev().enumerate() {
+        plot(digit as char, start + i, row, color);
+    }
+}
+
+// Plots at most `max_width` characters of `s`, so window contents can't spill
+// over the border into a neighboring window.
+fn plot_str_clamped(s: &str, col: usize, row: usize, max_width: usize, color: ColorCode) {
+    let end = s.char_indices().nth(max_width).map_or(s.len(), |(i, _)| i);
+    plot_str(&s[..end], col, row, color);
+}
+
+// Plots raw bytes one cell at a time, so text that isn't valid UTF-8 still
+// draws. NUL padding is blank; other bytes that can't be drawn show as
+// `UNDRAWABLE_PLACEHOLDER`.
+fn plot_bytes_clamped(bytes: &[u8], col: usize, row: usize, max_width: usize, color: ColorCode) {
+    for (i, &byte) in bytes.iter().take(max_width).enumerate() {
+        let c = match byte {
+            0 => ' ',
+            byte if is_drawable(byte as char) => byte as char,
+            _ => UNDRAWABLE_PLACEHOLDER,
+        };
+        plot(c, col + i, row, color);
+    }
+}
+
+// Plots `s` centered within a field of `width` columns starting at `col`.
+fn plot_str_centered(s: &str, col: usize, row: usize, width: usize, color: ColorCode) {
+    let padding = width.saturating_sub(s.len()) / 2;
+    plot_str_clamped(s, col + padding, row, width - padding, color);
+}
+
+// The VGA font is code page 437, and `plot` writes a char's low byte straight
+// into the buffer, so box-drawing glyphs are named by their CP437 codes.
+fn cp437(code: u8) -> char {
+    code as char
+}
+
+// Picks the glyph for one corner of a window's box border. The windows share
+// their inner edges, so a corner on a shared edge is a tee or a cross that
+// joins up with the neighbouring borders. The selected window is drawn last
+// in double lines and keeps plain double corners, and a maximized window,
+// `alone` on the screen, has plain corners too.
+fn box_corner(window: KWindows, left: bool, top: bool, selected: bool, alone: bool) -> char {
+    if selected {
+        return cp437(match (left, top) {
+            (true, true) => 0xC9,
+            (false, true) => 0xBB,
+            (true, false) => 0xC8,
+            (false, false) => 0xBC,
+        });
+    }
+    if alone {
+        return cp437(match (left, top) {
+            (true, true) => 0xDA,
+            (false, true) => 0xBF,
+            (true, false) => 0xC0,
+            (false, false) => 0xD9,
+        });
+    }
+    let left_window = window.col() == 0;
+    let top_window = window.row() == FIRST_BORDER_ROW;
+    let shared_col = left != left_window;
+    let shared_row = top != top_window;
+    let joins_left = !left || shared_col;
+    let joins_right = left || shared_col;
+    let joins_up = !top || shared_row;
+    let joins_down = top || shared_row;
+    cp437(match (joins_up, joins_down, joins_left, joins_right) {
+        (false, true, false, true) => 0xDA,
+        (false, true, true, false) => 0xBF,
+        (true, false, false, true) => 0xC0,
+        (true, false, true, false) => 0xD9,
+        (false, true, true, true) => 0xC2,
+        (true, false, true, true) => 0xC1,
+        (true, true, false, true) => 0xC3,
+        (true, true, true, false) => 0xB4,
+        _ => 0xC5,
+    })
+}
+
+fn text_color() -> ColorCode {
+    ColorCode::new(Color::White, Color::Black)
 }
-print((4 * sum))"#;
 
-// Seed the disk with some programs.
-fn initial_files(disk: &mut FileSystem<MAX_OPEN, BLOCK_SIZE, NUM_BLOCKS, MAX_FILE_BLOCKS, MAX_FILE_BYTES, MAX_FILES_STORED, MAX_FILENAME_BYTES>) {
-    for (filename, contents) in [
-        ("hello", HELLO),
-        ("nums", NUMS),
-        ("add_one", ADD_ONE),
-        ("countdown", COUNTDOWN),
-        ("average", AVERAGE),
-        ("pi", PI),
-    ] {
-        let fd = disk.open_create(filename).unwrap();
-        disk.write(fd, contents.as_bytes()).unwrap();
-        disk.close(fd);
-    }
+fn error_color() -> ColorCode {
+    ColorCode::new(Color::LightRed, Color::Black)
 }
 
-impl Kernel {
-    pub fn new() -> Self {
-        let mut fs: FileSystem<
-            MAX_OPEN, 
-            BLOCK_SIZE, 
-            NUM_BLOCKS, 
-            MAX_FILE_BLOCKS, 
-            MAX_FILE_BYTES, 
-            MAX_FILES_STORED, 
-            MAX_FILENAME_BYTES
-        > = FileSystem::new(ramdisk::RamDisk::new());
-        initial_files(&mut fs);
-        let filebar_buffer = TypingBuffer {
-            buffer: [0u8; MAX_FILENAME_BYTES],
-            cursor: 0,
+fn keyword_color() -> ColorCode {
+    ColorCode::new(Color::Yellow, Color::Black)
+}
+
+fn string_color() -> ColorCode {
+    ColorCode::new(Color::LightGreen, Color::Black)
+}
+
+fn number_color() -> ColorCode {
+    ColorCode::new(Color::LightCyan, Color::Black)
+}
+
+fn highlight_color() -> ColorCode {
+    ColorCode::new(Color::Black, Color::White)
+}
+
+// Host-side tests. Kernel tests go through `handle_key` and `perform`, which
+// only draw into the screen buffer; anything that calls `present` needs the
+// kernel running.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editing_state(text: &[u8]) -> EditingState {
+        let mut buffer = [0u8; PRACTICAL_FILE_BUFFER_SIZE];
+        buffer[..text.len()].copy_from_slice(text);
+        let mut filename = [0u8; MAX_FILENAME_BYTES];
+        filename[..4].copy_from_slice(b"test");
+        let KWindowMode::Editing(edit_state) = KWindowMode::editing(filename, buffer, text.len(), 0) else {
+            unreachable!()
         };
-        
-        Self {
-            selected: KSelection::Window(KWindows::F1),
-            filebar_buffer,
-            window_modes: [KWindowMode::directory(0); NUM_WINDOWS],
-            fs
-        }
+        edit_state
     }
 
-    pub fn key(&mut self, key: DecodedKey) {
-        match key {
-            DecodedKey::RawKey(code) => self.handle_raw(code),
-            DecodedKey::Unicode(c) => self.handle_unicode(c)
+    fn write_file(kernel: &mut Kernel, name: &str, contents: &[u8]) {
+        let fd = kernel.fs.open_create(name).unwrap();
+        kernel.fs.write(fd, contents).unwrap();
+        kernel.fs.close(fd).unwrap();
+        kernel.files_changed();
+    }
+
+    fn read_file(kernel: &mut Kernel, name: &str) -> Vec<u8> {
+        let fd = kernel.fs.open_read(name).unwrap();
+        let mut buffer = [0u8; MAX_FILE_BYTES];
+        let len = kernel.fs.read(fd, &mut buffer).unwrap();
+        kernel.fs.close(fd).unwrap();
+        buffer[..len].to_vec()
+    }
+
+    // Opens `name` for editing in `window`.
+    fn edit_file(kernel: &mut Kernel, window: KWindows, name: &str) {
+        let index = kernel.find_file(name).unwrap();
+        kernel.set_window_mode(window, KWindowMode::directory(index));
+        kernel.switch_to_edit_mode(window);
+    }
+
+    // What `switch_to_edit_mode` loads is what `switch_to_directory_mode`
+    // saves, byte for byte.
+    #[test]
+    fn trailing_newline_survives_open_and_save() {
+        let text = b"print(1)\nprint(257)\n";
+        let mut kernel = Kernel::new();
+        write_file(&mut kernel, "nl", text);
+        edit_file(&mut kernel, KWindows::F1, "nl");
+        assert_eq!(window_edit_state(&kernel, KWindows::F1).contents(), text);
+        kernel.switch_to_directory_mode(KWindows::F1);
+        assert!(matches!(kernel.get_window_mode(KWindows::F1), KWindowMode::Directory(_)));
+        assert_eq!(read_file(&mut kernel, "nl"), text);
+    }
+
+    #[test]
+    fn input_byte_lets_through_only_printable_ascii() {
+        assert_eq!(input_byte('a', false), Some(b'a'));
+        assert_eq!(input_byte('~', false), Some(b'~'));
+        assert_eq!(input_byte('\n', true), Some(b'\n'));
+        assert_eq!(input_byte('\n', false), None);
+        assert_eq!(input_byte('é', true), None);
+        assert_eq!(input_byte('\u{2500}', true), None);
+    }
+
+    #[test]
+    fn non_ascii_is_typed_into_neither_buffer() {
+        let mut filebar = TypingBuffer { buffer: [0u8; MAX_FILENAME_BYTES], cursor: 0 };
+        filebar.type_char('é');
+        assert_eq!(filebar.get_bytes().0, 0);
+        filebar.type_char('a');
+        assert_eq!(filebar.get_bytes().0, 1);
+
+        let mut edit_state = editing_state(b"");
+        edit_state.type_char('é');
+        assert_eq!(edit_state.contents(), b"");
+        edit_state.type_char('a');
+        assert_eq!(edit_state.contents(), b"a");
+    }
+
+    // Saving writes `contents()`, so nothing past the cut may come back.
+    #[test]
+    fn truncated_buffer_saves_only_what_is_left() {
+        let mut edit_state = editing_state(b"abc\ndef\nghi\n");
+        edit_state.cursor = 4;
+        edit_state.truncate_at_cursor();
+        assert_eq!(edit_state.contents(), b"abc\n");
+        assert!(edit_state.buffer[4..].iter().all(|&byte| byte == 0));
+        assert!(edit_state.dirty);
+        assert!(edit_state.scroll < edit_state.line_count(edit_state.width));
+    }
+
+    // The step count only depends on the program and its input, and each
+    // trip around countdown's loop costs the same number of steps.
+    #[test]
+    fn headless_step_count_is_the_same_every_run() {
+        let first = run_headless(COUNTDOWN, &["3"]).unwrap();
+        let second = run_headless(COUNTDOWN, &["3"]).unwrap();
+        assert_eq!(first.steps, second.steps);
+        assert_eq!(&first.bytes[..first.len], &second.bytes[..second.len]);
+        assert!(first.bytes[..first.len].ends_with(b"done\n0\n"));
+
+        let steps = |count| run_headless(COUNTDOWN, &[count]).unwrap().steps;
+        let per_trip = steps("4") - steps("3");
+        assert!(per_trip > 0);
+        assert_eq!(steps("7") - steps("3"), 4 * per_trip);
+        assert_eq!(steps("3") - steps("0"), 3 * per_trip);
+        assert_eq!(run_headless(COUNTDOWN, &[]).err(), Some("program needs input"));
+    }
+
+    fn stored_name(name: &[u8]) -> [u8; MAX_FILENAME_BYTES] {
+        let mut stored = [0u8; MAX_FILENAME_BYTES];
+        stored[..name.len()].copy_from_slice(name);
+        stored
+    }
+
+    #[test]
+    fn output_filename_appends_out_and_shortens_long_names() {
+        let (len, name) = output_filename(&stored_name(b"pi"));
+        assert_eq!(filename_str(&name[..len]), "pi.out");
+        let (len, name) = output_filename(&stored_name(b"countdown"));
+        assert_eq!(filename_str(&name[..len]), "countd.out");
+        let (len, name) = output_filename(&stored_name(b"abcdefghij"));
+        assert_eq!(len, MAX_FILENAME_BYTES);
+        assert_eq!(filename_str(&name), "abcdef.out");
+    }
+
+    #[test]
+    fn check_filename_refuses_empty_long_and_odd_names() {
+        assert_eq!(check_filename("cnt_2.txt"), Ok(()));
+        assert_eq!(check_filename("abcdefghij"), Ok(()));
+        assert_eq!(check_filename(""), Err("type a file name"));
+        assert_eq!(check_filename("abcdefghijk"), Err("file name too long"));
+        assert_eq!(check_filename("my file"), Err("names use letters, digits, _ and ."));
+        assert_eq!(check_filename("a/b"), Err("names use letters, digits, _ and ."));
+    }
+
+    // Running out of descriptors has to say so rather than fall into the
+    // catch-all.
+    #[test]
+    fn open_errors_name_what_ran_out() {
+        assert_eq!(open_error_status(FileSystemError::TooManyOpen), "too many open files");
+        assert_eq!(open_error_status(FileSystemError::TooManyFiles), "directory is full");
+        assert_eq!(open_error_status(FileSystemError::FileNotOpen), "couldn't open file");
+    }
+
+    #[test]
+    fn write_errors_say_the_file_was_not_saved() {
+        assert_eq!(write_error_status(FileSystemError::DiskFull), "disk full! file not saved");
+        assert_eq!(write_error_status(FileSystemError::FileTooBig), "file too big! not saved");
+        assert_eq!(write_error_status(FileSystemError::NotOpenForWrite), "couldn't write file");
+    }
+
+    // F11 repeats an editor insert as readily as a delete, and each delete it
+    // repeats still has to be confirmed.
+    #[test]
+    fn repeat_replays_snippets_and_deletes() {
+        let mut kernel = Kernel::with_startup([
+            StartupMode::Edit("hello"), StartupMode::Directory, StartupMode::Directory, StartupMode::Directory,
+        ]);
+        kernel.set_ticks(7);
+        kernel.handle_key(DecodedKey::Unicode(INSERT_TICKS_KEY));
+        kernel.handle_key(DecodedKey::RawKey(KeyCode::F11));
+        let KWindowMode::Editing(edit_state) = kernel.get_window_mode(KWindows::F1) else { panic!() };
+        assert!(edit_state.contents().ends_with(b")77"));
+
+        kernel.handle_key(DecodedKey::RawKey(KeyCode::F2));
+        kernel.handle_key(DecodedKey::Unicode('d'));
+        kernel.handle_key(DecodedKey::Unicode('d'));
+        assert_eq!(kernel.list_directory().0, 5);
+        kernel.handle_key(DecodedKey::RawKey(KeyCode::F11));
+        assert_eq!(kernel.list_directory().0, 5);
+        kernel.handle_key(DecodedKey::RawKey(KeyCode::F11));
+        assert_eq!(kernel.list_directory().0, 4);
+    }
+
+    // A maximized window's bottom border is the grid's, so the hint row is
+    // never drawn over, and its listing scrolls by its own height.
+    #[test]
+    fn maximized_window_ends_above_the_hint_row() {
+        let mut kernel = Kernel::new();
+        kernel.perform(Action::ToggleMaximize);
+        let frame = kernel.frame(KWindows::F1);
+        let grid_bottom = KWindows::F4.row() + WINDOW_HEIGHT + 1;
+        assert_eq!(frame.row + frame.height + 1, grid_bottom);
+        assert!(grid_bottom < HINT_ROW);
+        let KWindowMode::Directory(dir_state) = kernel.get_window_mode(KWindows::F1) else { panic!() };
+        assert_eq!(dir_state.height, MAX_WINDOW_HEIGHT);
+    }
+
+    // Finishes whatever `window` is running and returns what it printed.
+    fn run_to_end(kernel: &mut Kernel, window: KWindows) -> WindowOutput {
+        for _ in 0..MAX_HEADLESS_STEPS {
+            kernel.step_window(window);
         }
-        self.draw();
+        let KWindowMode::Running(run_state) = kernel.get_window_mode(window) else { panic!() };
+        assert!(run_state.status == ProcessStatus::Finished);
+        run_state.output
     }
 
-    fn handle_raw(&mut self, key: KeyCode) {
-        match key {
-            KeyCode::F1 => self.selected = KSelection::Window(KWindows::F1),
-            KeyCode::F2 => self.selected = KSelection::Window(KWindows::F2),
-            KeyCode::F3 => self.selected = KSelection::Window(KWindows::F3),
-            KeyCode::F4 => self.selected = KSelection::Window(KWindows::F4),
-            KeyCode::F5 => self.selected = KSelection::Filebar,
-            KeyCode::F6 => {
-                if let KSelection::Window(window) = self.selected {
-                    self.switch_to_directory_mode(window);
-                }
-            },
-            KeyCode::F7 => self.scroll_edit_text(-1),
-            KeyCode::F8 => self.scroll_edit_text(1),
-            KeyCode::ArrowUp    => self.move_dir_cursor(-3),
-            KeyCode::ArrowDown  => self.move_dir_cursor(3),
-            KeyCode::ArrowLeft  => self.move_dir_cursor(-1),
-            KeyCode::ArrowRight => self.move_dir_cursor(1),
-            _ => {}
+    // A rerun starts from the source the run kept, so it prints the same
+    // lines again even once the file is gone.
+    #[test]
+    fn rerun_repeats_the_same_output_without_the_file() {
+        let mut kernel = Kernel::with_startup([
+            StartupMode::Run("nums"), StartupMode::Directory, StartupMode::Directory, StartupMode::Directory,
+        ]);
+        let first = run_to_end(&mut kernel, KWindows::F1);
+        assert_eq!(first.count, 2);
+        assert_eq!(&first.lines[1][..first.lens[1]], b"257");
+        kernel.fs.delete("nums").unwrap();
+        for _ in 0..2 {
+            kernel.handle_key(DecodedKey::Unicode('r'));
+            let again = run_to_end(&mut kernel, KWindows::F1);
+            assert_eq!(again.count, first.count);
+            assert_eq!(again.lens, first.lens);
+            assert!(again.lines == first.lines);
         }
     }
 
-    fn handle_unicode(&mut self, key: char) {
-        match self.selected {
-            KSelection::Filebar => {
-                match key {
-                    '\u{8}' => self.filebar_buffer.backspace(),
-                    '\n' => self.try_create_file(),
-                    other if is_drawable(other) => self.filebar_buffer.type_char(other),
-                    _ => {},
-                }
-            },
-            KSelection::Window(window) => {
-                match self.get_window_mode(window) {
-                    KWindowMode::Directory(_) => {
-                        match key {
-                            'e' => self.switch_to_edit_mode(window),
-                            'r' => self.switch_to_run_mode(window),
-                            _ => {},
-                        }
-                    },
-                    KWindowMode::Editing(mut edit_state) => {
-                        match key {
-                            '\n' => edit_state.type_char('\n'),
-                            key if is_drawable(key) => edit_state.type_char(key),
-                            '\u{8}' => edit_state.backspace(),
-                            _ => {},
-                        }
-                        self.set_window_mode(window, KWindowMode::Editing(edit_state));
-                    },
-                    KWindowMode::Running(_) => {
-                        todo!("handle unicode for a running window")
-                    },
-                }
-            },
+    // Bytes that aren't UTF-8 are drawn one at a time as placeholders.
+    #[test]
+    fn invalid_utf8_is_drawn_without_panicking() {
+        let edit_state = editing_state(b"x := \xff\xfe\nprint(x)\xff");
+        draw_edit_text(&edit_state, 0, FIRST_BORDER_ROW, text_color());
+    }
+
+    #[test]
+    fn typing_mid_buffer_shifts_the_rest_along() {
+        let mut edit_state = editing_state(b"abc");
+        edit_state.cursor = 1;
+        edit_state.type_char('x');
+        assert_eq!(edit_state.contents(), b"axbc");
+        assert_eq!(edit_state.len, 4);
+        assert_eq!(edit_state.cursor, 2);
+    }
+
+    // Backspacing leaves nothing behind past `len` for a save to pick up.
+    #[test]
+    fn backspace_closes_the_gap_mid_line_and_at_the_end() {
+        let mut edit_state = editing_state(b"abcd");
+        edit_state.cursor = 2;
+        edit_state.backspace();
+        assert_eq!(edit_state.contents(), b"acd");
+        assert_eq!(edit_state.cursor, 1);
+        edit_state.cursor = edit_state.len;
+        edit_state.backspace();
+        assert_eq!(edit_state.contents(), b"ac");
+        assert_eq!(edit_state.cursor, 2);
+        assert!(edit_state.buffer[2..].iter().all(|&byte| byte == 0));
+        edit_state.cursor = 0;
+        edit_state.backspace();
+        assert_eq!(edit_state.contents(), b"ac");
+    }
+
+    #[test]
+    fn undo_then_redo_and_a_new_edit_drops_the_redo() {
+        let mut edit_state = editing_state(b"x := 1\n");
+        for c in "done".chars() {
+            edit_state.type_char(c);
         }
+        edit_state.undo().unwrap();
+        assert_eq!(edit_state.contents(), b"x := 1\n");
+        assert_eq!(edit_state.cursor, 7);
+        edit_state.redo().unwrap();
+        assert_eq!(edit_state.contents(), b"x := 1\ndone");
+        assert_eq!(edit_state.cursor, 11);
+        edit_state.undo().unwrap();
+        edit_state.type_char('y');
+        assert_eq!(edit_state.redo(), Err("nothing to redo"));
+        assert_eq!(edit_state.contents(), b"x := 1\ny");
     }
 
-    pub fn draw(&mut self) {
-        plot_str(FILENAME_PROMPT, 0, 0, text_color());
-        self.filebar_buffer.draw(FILENAME_PROMPT.len(), 0, text_color());
-        for window in [KWindows::F1, KWindows::F2, KWindows::F3, KWindows::F4] {
-            self.draw_window(window);
+    #[test]
+    fn cut_middle_line_and_duplicate_first_line() {
+        let mut edit_state = editing_state(b"one\ntwo\nthree");
+        edit_state.cursor = 5;
+        edit_state.cut_line().unwrap();
+        assert_eq!(edit_state.contents(), b"one\nthree");
+        assert_eq!(edit_state.cursor, 4);
+
+        edit_state.cursor = 1;
+        edit_state.duplicate_line().unwrap();
+        assert_eq!(edit_state.contents(), b"one\none\nthree");
+        assert_eq!(edit_state.cursor, 5);
+        edit_state.undo().unwrap();
+        assert_eq!(edit_state.contents(), b"one\nthree");
+    }
+
+    #[test]
+    fn reindent_halves_nested_indents_and_keeps_the_cursor() {
+        let mut edit_state = editing_state(
+            b"while (i < 3) {\n    if (i > 1) {\n        print(i)\n    }\n    i := (i + 1)\n}\n",
+        );
+        edit_state.cursor = 41;
+        assert!(edit_state.contents()[edit_state.cursor..].starts_with(b"print"));
+        edit_state.reindent(2).unwrap();
+        assert_eq!(
+            edit_state.contents(),
+            b"while (i < 3) {\n  if (i > 1) {\n    print(i)\n  }\n  i := (i + 1)\n}\n",
+        );
+        assert!(edit_state.contents()[edit_state.cursor..].starts_with(b"print"));
+        assert!(edit_state.dirty);
+    }
+
+    // Where wrapping and newlines meet: a full line ends its row without an
+    // extra blank one, and a newline after it still starts exactly one line.
+    #[test]
+    fn wrapping_at_the_window_width() {
+        let mut text = [b'a'; WINDOW_WIDTH + 3];
+        let exact = editing_state(&text[..WINDOW_WIDTH]);
+        assert_eq!(exact.line_count(exact.width), 1);
+        assert_eq!(exact.read_line(0).unwrap()[..WINDOW_WIDTH], text[..WINDOW_WIDTH]);
+        assert_eq!(exact.read_line(1), None);
+
+        let over = editing_state(&text[..WINDOW_WIDTH + 1]);
+        assert_eq!(over.line_count(over.width), 2);
+        assert_eq!(over.read_line(1).unwrap()[..2], *b"a ");
+
+        text[WINDOW_WIDTH] = b'\n';
+        let newline = editing_state(&text[..WINDOW_WIDTH + 1]);
+        assert_eq!(newline.line_count(newline.width), 2);
+        assert_eq!(newline.read_line(0).unwrap()[..WINDOW_WIDTH], text[..WINDOW_WIDTH]);
+        assert!(newline.read_line(1).unwrap()[..WINDOW_WIDTH].iter().all(|&byte| byte == b' '));
+
+        text[WINDOW_WIDTH + 1] = b'\n';
+        let blank = editing_state(&text[..WINDOW_WIDTH + 3]);
+        assert_eq!(blank.line_count(blank.width), 3);
+        assert!(blank.read_line(1).unwrap()[..WINDOW_WIDTH].iter().all(|&byte| byte == b' '));
+        assert_eq!(blank.read_line(2).unwrap()[..2], *b"a ");
+    }
+
+    #[test]
+    fn line_count_agrees_with_read_line() {
+        let edit_state = editing_state(b"a\nb\nc");
+        assert_eq!(edit_state.line_count(edit_state.width), 3);
+        let lines = (0..).take_while(|&line| edit_state.read_line(line).is_some()).count();
+        assert_eq!(lines, 3);
+        assert_eq!(edit_state.read_line(2).unwrap()[0], b'c');
+    }
+
+    // A kernel with F1 selected and editing `text`.
+    fn editing_kernel(text: &[u8]) -> Kernel {
+        let mut kernel = Kernel::new();
+        kernel.set_window_mode(KWindows::F1, KWindowMode::Editing(editing_state(text)));
+        kernel
+    }
+
+    fn window_edit_state(kernel: &Kernel, window: KWindows) -> EditingState {
+        let KWindowMode::Editing(edit_state) = kernel.get_window_mode(window) else { panic!("not editing") };
+        edit_state
+    }
+
+    #[test]
+    fn tab_types_four_spaces() {
+        let mut kernel = editing_kernel(b"x");
+        kernel.handle_key(DecodedKey::Unicode('\t'));
+        let edit_state = window_edit_state(&kernel, KWindows::F1);
+        assert_eq!(edit_state.contents(), b"x    ");
+        assert_eq!(edit_state.len, 1 + TAB_SPACES);
+        assert_eq!(edit_state.cursor, 1 + TAB_SPACES);
+    }
+
+    // Whether `message` is showing in the status line or waiting its turn.
+    fn status_has(kernel: &Kernel, message: &str) -> bool {
+        kernel.status.messages[..kernel.status.count].contains(&message)
+    }
+
+    #[test]
+    fn typing_into_a_full_buffer_is_refused_and_said() {
+        let mut kernel = editing_kernel(&[b'a'; PRACTICAL_FILE_BUFFER_SIZE]);
+        for key in ['b', '\n', '\t'] {
+            kernel.handle_key(DecodedKey::Unicode(key));
         }
-        if let KSelection::Window(window) = self.selected {
-            self.draw_window(window)
+        let edit_state = window_edit_state(&kernel, KWindows::F1);
+        assert_eq!(edit_state.len, PRACTICAL_FILE_BUFFER_SIZE);
+        assert!(edit_state.contents().iter().all(|&byte| byte == b'a'));
+        assert!(!edit_state.dirty);
+        assert!(status_has(&kernel, "edit buffer full"));
+    }
+
+    // Paging moves by a window's height and stops on the last line.
+    #[test]
+    fn page_down_and_up_scroll_by_the_window_height() {
+        let mut text = [0u8; 60];
+        for (line, pair) in text.chunks_mut(2).enumerate() {
+            pair.copy_from_slice(&[b'a' + line as u8 % 26, b'\n']);
         }
-        for window in [KWindows::F1, KWindows::F2, KWindows::F3, KWindows::F4] {
-            plot_str(
-                window.name(),
-                window.col() + WINDOW_LABEL_COL_OFFSET,
-                window.row(),
-                text_color(),
-            );
+        let mut edit_state = editing_state(&text[..59]);
+        edit_state.cursor = 0;
+        edit_state.scroll = 0;
+        let mut kernel = Kernel::new();
+        kernel.set_window_mode(KWindows::F1, KWindowMode::Editing(edit_state));
+        for (key, scroll) in [
+            (KeyCode::PageDown, WINDOW_HEIGHT),
+            (KeyCode::PageDown, 2 * WINDOW_HEIGHT),
+            (KeyCode::PageDown, 29),
+            (KeyCode::PageUp, 29 - WINDOW_HEIGHT),
+        ] {
+            kernel.handle_key(DecodedKey::RawKey(key));
+            assert_eq!(window_edit_state(&kernel, KWindows::F1).scroll, scroll);
         }
     }
 
-    pub fn draw_proc_status(&mut self) {
-        // todo!("Draw processor status");
+    fn type_keys(kernel: &mut Kernel, text: &str) {
+        for key in text.chars() {
+            kernel.handle_key(DecodedKey::Unicode(key));
+        }
     }
 
-    pub fn run_one_instruction(&mut self) {
-        // todo!("Run an instruction in a process");
+    // Search and replace text can run longer than a filename.
+    #[test]
+    fn replace_all_renames_countdowns_variable() {
+        let mut kernel = editing_kernel(COUNTDOWN.as_bytes());
+        kernel.handle_key(DecodedKey::Unicode(REPLACE_KEY));
+        type_keys(&mut kernel, "count/n/g\n");
+        let edit_state = window_edit_state(&kernel, KWindows::F1);
+        assert_eq!(edit_state.contents(), COUNTDOWN.replace("count", "n").as_bytes());
+        assert_eq!(kernel.selected, KSelection::Window(KWindows::F1));
+
+        kernel.handle_key(DecodedKey::Unicode(SEARCH_KEY));
+        type_keys(&mut kernel, "print(\"done\")\n");
+        let edit_state = window_edit_state(&kernel, KWindows::F1);
+        assert!(edit_state.contents()[edit_state.cursor..].starts_with(b"print(\"done\")"));
     }
 
-    fn draw_window(&mut self, window: KWindows) {
-        self.clear_window(window);
-        self.draw_window_border(window);
-        let col = window.col();
-        let row = window.row();
-        match self.get_window_mode(window) {
-            KWindowMode::Directory(dir_state) => {
-                let (file_count, filenames) = self.fs.list_directory().unwrap();
-                let mut file_col_offset = 1;
-                let mut file_row_offset = 1;
-                for file in 0..file_count {
-                    let filename_bytes = filenames[file];
-                    for byte in filename_bytes {
-                        let color = if file == dir_state.cursor { highlight_color() } else { text_color() };
-                        plot(byte as char, col + file_col_offset, row + file_row_offset, color);
-                        file_col_offset += 1;
-                    }
-                    if file_col_offset > 3 * MAX_FILENAME_BYTES {
-                        file_col_offset = 1;
-                        file_row_offset += 1;
-                    }
-                }
-            },
-            KWindowMode::Editing(edit_state) => {
-                plot_str(EDIT_MODE_HEADER, col + FILENAME_LABEL_COL_OFFSET, row, text_color());
-                for i in 0..edit_state.filename.len() {
-                    if edit_state.filename[i] == 0 { continue }
-                    plot(
-                        edit_state.filename[i] as char,
-                        col + i + EDIT_MODE_HEADER.len() + FILENAME_LABEL_COL_OFFSET,
-                        row,
-                        text_color()
-                    );
-                }
-                for line in 0..WINDOW_HEIGHT {
-                    if let Some(line_bytes) = edit_state.read_line(edit_state.scroll + line) {
-                        let line_str = str::from_utf8(&line_bytes).unwrap();
-                        plot_str(line_str, col + 1, row + 1 + line, text_color());
-                    } else {
-                        continue
-                    }
-                }
-            },
-            KWindowMode::Running(_) => {
-                todo!("draw a running window")
-            },
-        }
+    // Opens `count` new files and leaves them open, taking that many
+    // descriptors.
+    fn hold_open_files(kernel: &mut Kernel, count: usize) -> Vec<usize> {
+        (0..count).map(|i| kernel.fs.open_create(&format!("held{i}")).unwrap()).collect()
     }
 
-    fn draw_window_border(&mut self, window: KWindows) {
-        let col = window.col();
-        let row = window.row();
-        let border = if let KSelection::Window(selected_win) = self.selected {
-            if selected_win == window {'*'} else {'.'}
-        } else {'.'};
-        for col_offset in 0..WINDOW_WIDTH+2 {
-            plot(border, col + col_offset, row, text_color());
-            plot(border, col + col_offset, row + WINDOW_HEIGHT+1, text_color());
+    // Running out of descriptors leaves the window in its directory, says
+    // why, and takes no descriptor of its own.
+    #[test]
+    fn opening_with_no_descriptors_left_is_reported() {
+        let mut kernel = Kernel::new();
+        let held = hold_open_files(&mut kernel, MAX_OPEN);
+        kernel.files_changed();
+        kernel.switch_to_edit_mode(KWindows::F1);
+        assert!(matches!(kernel.get_window_mode(KWindows::F1), KWindowMode::Directory(_)));
+        assert!(status_has(&kernel, "too many open files"));
+        for fd in held {
+            kernel.fs.close(fd).unwrap();
         }
-        for row_offset in 0..WINDOW_HEIGHT+2 {
-            plot(border, col, row + row_offset, text_color());
-            plot(border, col + WINDOW_WIDTH+1, row + row_offset, text_color());
+        for i in 0..MAX_OPEN {
+            kernel.fs.open_read(&format!("held{i}")).unwrap();
         }
     }
 
-    fn clear_window(&mut self, window: KWindows) {
-        let col = window.col();
-        let row = window.row();
-        for col_offset in 1..WINDOW_WIDTH+1 {
-            for row_offset in 1..WINDOW_HEIGHT+1 {
-                plot(' ', col + col_offset, row + row_offset, text_color());
-            }
+    // Writes files until no block is left free.
+    fn fill_disk(kernel: &mut Kernel) {
+        for i in 0.. {
+            let fd = kernel.fs.open_create(&format!("fill{i}")).unwrap();
+            let full = (0..MAX_FILE_BLOCKS).any(|_| kernel.fs.write(fd, &[b'x'; BLOCK_SIZE]) == Err(FileSystemError::DiskFull));
+            kernel.fs.close(fd).unwrap();
+            if full { break }
         }
+        kernel.files_changed();
     }
 
-    fn try_create_file(&mut self) {
-        let (name_len, name_bytes) = self.filebar_buffer.get_bytes();
-        self.filebar_buffer.clear();
-        if let Ok(str) = str::from_utf8(&name_bytes[0..name_len]) {
-            let new_file = self.fs.open_create(str).unwrap();
-            self.fs.close(new_file).unwrap();
+    // A save the disk can't hold keeps the buffer dirty and says so.
+    #[test]
+    fn failed_save_leaves_the_buffer_dirty() {
+        let mut kernel = Kernel::with_startup([
+            StartupMode::Edit("hello"), StartupMode::Directory, StartupMode::Directory, StartupMode::Directory,
+        ]);
+        let mut edit_state = window_edit_state(&kernel, KWindows::F1);
+        for _ in 0..3 * BLOCK_SIZE {
+            edit_state.type_char('x');
         }
+        kernel.set_window_mode(KWindows::F1, KWindowMode::Editing(edit_state));
+        fill_disk(&mut kernel);
+        assert!(!kernel.save_editing_file(KWindows::F1));
+        let edit_state = window_edit_state(&kernel, KWindows::F1);
+        assert!(edit_state.dirty);
+        assert_eq!(edit_state.len, HELLO.len() + 3 * BLOCK_SIZE);
+        assert!(status_has(&kernel, "disk full! file not saved"));
     }
 
-    fn get_window_mode(&self, window: KWindows) -> KWindowMode {
-        match window {
-            KWindows::F1 => self.window_modes[0],
-            KWindows::F2 => self.window_modes[1],
-            KWindows::F3 => self.window_modes[2],
-            KWindows::F4 => self.window_modes[3],
-        }
+    // The fold key folds the block on the cursor's line, wherever the
+    // window is scrolled to.
+    #[test]
+    fn fold_toggles_the_block_on_the_cursor_line() {
+        let mut edit_state = editing_state(COUNTDOWN.as_bytes());
+        edit_state.scroll = 0;
+        edit_state.cursor = 0;
+        assert_eq!(edit_state.toggle_fold(), Err("no block opens on this line"));
+        edit_state.move_cursor(Direction::Down);
+        edit_state.toggle_fold().unwrap();
+        assert_eq!(edit_state.fold_count, 1);
+        assert_eq!(edit_state.folds[0], COUNTDOWN.find('{').unwrap());
+        assert_eq!(edit_state.line_count(edit_state.width), 4);
+        edit_state.toggle_fold().unwrap();
+        assert_eq!(edit_state.fold_count, 0);
     }
 
-    fn set_window_mode(&mut self, window: KWindows, mode: KWindowMode) {
-        let index = match window {
-            KWindows::F1 => 0,
-            KWindows::F2 => 1,
-            KWindows::F3 => 2,
-            KWindows::F4 => 3,
-        };
-        self.window_modes[index] = mode;
+    // F1 editing `hello` with an unsaved edit, and F2 editing `nums` as it
+    // was loaded.
+    fn kernel_with_unsaved_edit() -> Kernel {
+        let mut kernel = Kernel::with_startup([
+            StartupMode::Edit("hello"), StartupMode::Edit("nums"), StartupMode::Directory, StartupMode::Directory,
+        ]);
+        kernel.handle_key(DecodedKey::Unicode('!'));
+        kernel
     }
 
-    fn move_dir_cursor(&mut self, delta: isize) {
-        if let KSelection::Window(window) = self.selected {
-            if let KWindowMode::Directory(mut dir_state) = self.get_window_mode(window) {
-                let (file_count, _) = self.fs.list_directory().unwrap();
-                dir_state.move_cursor(delta, file_count);
-                self.set_window_mode(window, KWindowMode::Directory(dir_state));
-            }
-        }
+    #[test]
+    fn reset_with_unsaved_edits_warns_then_discards() {
+        let mut kernel = kernel_with_unsaved_edit();
+        kernel.handle_key(DecodedKey::RawKey(KeyCode::F12));
+        assert!(status_has(&kernel, "unsaved edits! repeat to discard"));
+        assert!(window_edit_state(&kernel, KWindows::F1).dirty);
+        kernel.handle_key(DecodedKey::RawKey(KeyCode::F12));
+        assert!(matches!(kernel.get_window_mode(KWindows::F1), KWindowMode::Directory(_)));
+        assert_eq!(read_file(&mut kernel, "hello"), HELLO.as_bytes());
     }
 
-    fn scroll_edit_text(&mut self, delta: isize) {
-        if let KSelection::Window(window) = self.selected {
-            if let KWindowMode::Editing(mut edit_state) = self.get_window_mode(window) {
-                edit_state.scroll = edit_state.scroll.saturating_add_signed(delta);
-                let line_count = edit_state.line_count(WINDOW_WIDTH);
-                if edit_state.scroll >= line_count {
-                    edit_state.scroll = line_count - 1;
-                }
-                self.set_window_mode(window, KWindowMode::Editing(edit_state));
-            }
-        }
+    #[test]
+    fn reset_under_autosave_saves_only_dirty_buffers() {
+        let mut kernel = kernel_with_unsaved_edit();
+        kernel.unsaved_policy = UnsavedPolicy::AutoSave;
+        // Changed behind F2's clean buffer, which mustn't write over it.
+        write_file(&mut kernel, "nums", b"print(2)");
+        kernel.handle_key(DecodedKey::RawKey(KeyCode::F12));
+        assert!(matches!(kernel.get_window_mode(KWindows::F1), KWindowMode::Directory(_)));
+        assert_eq!(read_file(&mut kernel, "hello"), format!("{HELLO}!").as_bytes());
+        assert_eq!(read_file(&mut kernel, "nums"), b"print(2)");
     }
 
-    fn switch_to_edit_mode(&mut self, window: KWindows) {
-        if let KWindowMode::Directory(dir_state) = self.get_window_mode(window) {
-            let chosen_file = dir_state.cursor;
-            let (file_count, directory) = self.fs.list_directory().unwrap();
-            assert!(chosen_file < file_count);
-            let filename_str = str::from_utf8(&directory[chosen_file]).unwrap();
-            let file = self.fs.open_read(filename_str).unwrap();
-            let mut buffer = [0u8; PRACTICAL_FILE_BUFFER_SIZE];
-            let filesize = self.fs.read(file, &mut buffer).unwrap();
-            self.fs.close(file);
-            self.set_window_mode(
-                window,
-                KWindowMode::editing(directory[chosen_file], buffer, filesize, chosen_file),
-            );
+    // Output follows focus unless the keyboard is busy answering something.
+    #[test]
+    fn output_does_not_take_focus_from_the_filebar() {
+        let mut kernel = Kernel::with_startup([
+            StartupMode::Directory, StartupMode::Run("nums"), StartupMode::Run("nums"), StartupMode::Directory,
+        ]);
+        kernel.focus_follows_output = true;
+        kernel.handle_key(DecodedKey::RawKey(KeyCode::F5));
+        assert!(kernel.step_window(KWindows::F2));
+        assert_eq!(kernel.selected, KSelection::Filebar);
+        kernel.handle_key(DecodedKey::RawKey(KeyCode::F1));
+        assert!(kernel.step_window(KWindows::F3));
+        assert_eq!(kernel.selected, KSelection::Window(KWindows::F3));
+    }
+
+    // More output than a window holds is drawn from the newest line back, in
+    // a quarter window and a maximized one alike.
+    #[test]
+    fn long_output_draws_within_any_frame() {
+        let program = "i := 0\nwhile (i < 40) {\n    print(i)\n    i := (i + 1)\n}";
+        let KWindowMode::Running(mut run_state) = KWindowMode::running(stored_name(b"count40"), program, false) else {
+            unreachable!()
+        };
+        while run_state.status == ProcessStatus::Runnable {
+            run_state.step(0);
+        }
+        assert!(run_state.status == ProcessStatus::Finished);
+        assert!(run_state.output.count > MAX_WINDOW_HEIGHT);
+        for frame in [
+            Frame { col: KWindows::F4.col(), row: KWindows::F4.row(), width: WINDOW_WIDTH, height: WINDOW_HEIGHT },
+            Frame { col: 0, row: FIRST_BORDER_ROW, width: MAX_WINDOW_WIDTH, height: MAX_WINDOW_HEIGHT },
+        ] {
+            draw_running_window(&run_state, frame, text_color());
         }
     }
 
-    fn switch_to_directory_mode(&mut self, window: KWindows) {
-        if let KWindowMode::Editing(edit_state) = self.get_window_mode(window) {
-            let filename_str = str::from_utf8(&edit_state.filename).unwrap();
-            let file = self.fs.open_create(filename_str).unwrap();
-            self.fs.write(file, &edit_state.buffer[0..edit_state.len]).unwrap();
-            self.fs.close(file).unwrap();
-            self.set_window_mode(
-                window,
-                KWindowMode::directory(edit_state.directory_index),
-            );
+    // A file deleted after its listing was read is reported, and the window
+    // stays on its directory with the cursor moved onto a file that's left.
+    #[test]
+    fn opening_a_deleted_file_stays_in_the_directory() {
+        let mut kernel = Kernel::new();
+        let (file_count, names) = kernel.list_directory();
+        let last = file_count - 1;
+        kernel.fs.delete(filename_str(&names[last])).unwrap();
+        kernel.set_window_mode(KWindows::F1, KWindowMode::directory(last));
+        kernel.switch_to_edit_mode(KWindows::F1);
+        let KWindowMode::Directory(dir_state) = kernel.get_window_mode(KWindows::F1) else { panic!() };
+        assert_eq!(dir_state.cursor, last - 1);
+        assert!(status_has(&kernel, "file no longer exists"));
+
+        kernel.fs.delete(filename_str(&names[last - 1])).unwrap();
+        kernel.switch_to_run_mode(KWindows::F1, false);
+        let KWindowMode::Directory(dir_state) = kernel.get_window_mode(KWindows::F1) else { panic!() };
+        assert_eq!(dir_state.cursor, last - 2);
+    }
+
+    // Saving a shorter buffer over a longer file leaves no old tail behind.
+    #[test]
+    fn shorter_save_leaves_exactly_len_bytes() {
+        let mut kernel = Kernel::new();
+        edit_file(&mut kernel, KWindows::F1, "pi");
+        let mut edit_state = window_edit_state(&kernel, KWindows::F1);
+        edit_state.cursor = HELLO.len();
+        edit_state.truncate_at_cursor();
+        edit_state.buffer[..HELLO.len()].copy_from_slice(HELLO.as_bytes());
+        kernel.set_window_mode(KWindows::F1, KWindowMode::Editing(edit_state));
+        kernel.switch_to_directory_mode(KWindows::F1);
+        assert_eq!(read_file(&mut kernel, "pi"), HELLO.as_bytes());
+        edit_file(&mut kernel, KWindows::F1, "pi");
+        assert_eq!(window_edit_state(&kernel, KWindows::F1).len, HELLO.len());
+    }
+
+    // A short name is created and saved under exactly its own bytes, so
+    // saving finds the same file instead of making a NUL-padded twin.
+    #[test]
+    fn short_names_are_stored_without_padding() {
+        let mut kernel = Kernel::new();
+        kernel.handle_key(DecodedKey::RawKey(KeyCode::F5));
+        type_keys(&mut kernel, "abc\n");
+        let file_count = kernel.list_directory().0;
+        let index = kernel.find_file("abc").unwrap();
+        assert_eq!(kernel.list_directory().1[index], stored_name(b"abc"));
+        edit_file(&mut kernel, KWindows::F1, "abc");
+        kernel.handle_key(DecodedKey::Unicode('x'));
+        kernel.switch_to_directory_mode(KWindows::F1);
+        assert_eq!(kernel.list_directory().0, file_count);
+        assert_eq!(read_file(&mut kernel, "abc"), b"x");
+    }
+
+    // A create the directory has no room for says so and keeps the name.
+    #[test]
+    fn failed_create_keeps_the_typed_name() {
+        let mut kernel = Kernel::new();
+        for i in kernel.list_directory().0..MAX_FILES_STORED {
+            write_file(&mut kernel, &format!("full{i}"), b"");
         }
+        kernel.handle_key(DecodedKey::RawKey(KeyCode::F5));
+        type_keys(&mut kernel, "spare\n");
+        assert!(status_has(&kernel, "directory is full"));
+        assert_eq!(kernel.selected, KSelection::Filebar);
+        let (len, name) = kernel.filebar_buffer.get_bytes();
+        assert_eq!(&name[..len], b"spare");
+        assert_eq!(kernel.find_file("spare"), None);
     }
 
-    fn switch_to_run_mode(&mut self, window:KWindows) {
-        if let KWindowMode::Directory(dir_state) = self.get_window_mode(window) {
-            let chosen_file = dir_state.cursor;
-            let (file_count, directory) = self.fs.list_directory().unwrap();
-            assert!(chosen_file < file_count);
-            let filename_str = str::from_utf8(&directory[chosen_file]).unwrap();
-            let file = self.fs.open_read(filename_str).unwrap();
-            let mut buffer = [0u8; PRACTICAL_FILE_BUFFER_SIZE];
-            let filesize = self.fs.read(file, &mut buffer).unwrap();
-            self.fs.close(file);
-            let program = str::from_utf8(&buffer[..filesize]).unwrap();
-            self.set_window_mode(
-                window,
-                KWindowMode::running(program),
-            );
+    // Every file fits in a whole window, so this listing is given room for
+    // only two rows to make it scroll.
+    #[test]
+    fn cursoring_past_the_bottom_row_scrolls_the_listing() {
+        let mut dir_state = DirectoryState::new(0);
+        dir_state.height = 2;
+        let file_count = 4 * DIRECTORY_COLUMNS;
+        for _ in 0..2 {
+            dir_state.move_cursor(DIRECTORY_COLUMNS as isize, file_count);
         }
+        assert_eq!(dir_state.cursor, 2 * DIRECTORY_COLUMNS);
+        assert_eq!(dir_state.scroll, 1);
+        dir_state.move_cursor(DIRECTORY_COLUMNS as isize, file_count);
+        assert_eq!(dir_state.scroll, 2);
+        for _ in 0..3 {
+            dir_state.move_cursor(-(DIRECTORY_COLUMNS as isize), file_count);
+        }
+        assert_eq!((dir_state.cursor, dir_state.scroll), (0, 0));
     }
-}
 
-fn text_color() -> ColorCode {
-    ColorCode::new(Color::White, Color::Black)
-}
+    // Nothing makes the file system fail a read of an open file, so a closed
+    // descriptor stands in for one, and a binary file for a read that can't
+    // be run. Either way the window stays on its directory.
+    #[test]
+    fn failed_read_leaves_the_window_in_its_directory() {
+        let mut kernel = Kernel::new();
+        let fd = kernel.fs.open_read("hello").unwrap();
+        kernel.fs.close(fd).unwrap();
+        assert!(kernel.read_program(fd).is_none());
+        assert!(status_has(&kernel, "couldn't read file"));
 
-fn highlight_color() -> ColorCode {
-    ColorCode::new(Color::Black, Color::White)
+        write_file(&mut kernel, "bin", &[0x7f, b'E', 0, 0xff]);
+        let index = kernel.find_file("bin").unwrap();
+        kernel.set_window_mode(KWindows::F1, KWindowMode::directory(index));
+        kernel.switch_to_run_mode(KWindows::F1, false);
+        assert!(matches!(kernel.get_window_mode(KWindows::F1), KWindowMode::Directory(_)));
+        assert!(status_has(&kernel, "not a text program"));
+        assert_eq!(kernel.run_counts[0], 0);
+    }
 }